@@ -1,8 +1,29 @@
 @group(0) @binding(0) var<uniform> params: Params;
 @group(0) @binding(1) var output_tex: texture_storage_2d<rgba8unorm, read_write>;
 @group(0) @binding(2) var<storage, read> spheres: array<Sphere>;
-@group(0) @binding(3) var<storage, read> triangle_vertices: array<vec4f>;
+@group(0) @binding(3) var<storage, read> triangle_vertices: array<Vertex>;
 @group(0) @binding(4) var<storage, read> triangle_meshes: array<TriangleMesh>;
+@group(0) @binding(5) var<storage, read> bvh_nodes: array<BvhNode>;
+// sphere indices, then mesh indices offset by sphere_count, in top-level leaf order
+@group(0) @binding(6) var<storage, read> tlas_objects: array<u32>;
+// three per triangle, relative to the mesh's base_vertex
+@group(0) @binding(7) var<storage, read> triangle_indices: array<u32>;
+// every texture scaled to the same size, indexed by the material's texture fields
+@group(0) @binding(8) var textures: texture_2d_array<f32>;
+@group(0) @binding(9) var texture_sampler: sampler;
+// emissive spheres and triangles and analytic lights, picked by their cdf
+@group(0) @binding(10) var<storage, read> lights: array<Light>;
+// equirectangular radiance, used when params.environment is set
+@group(0) @binding(11) var environment: texture_2d<f32>;
+// each row of the environment map's conditional cdf, then its marginal cdf as an extra row
+@group(0) @binding(12) var environment_cdf: texture_2d<f32>;
+// lights without a surface, which lights refer to with ANALYTIC_LIGHT
+@group(0) @binding(13) var<storage, read> analytic_lights: array<AnalyticLight>;
+// relative intensity of each ies profile, vertical angles across and horizontal ones down,
+// one profile after another
+@group(0) @binding(14) var profiles: texture_2d<f32>;
+// values that are all different and evenly spread out, for the blue-noise sampler
+@group(0) @binding(15) var blue_noise_tile: texture_2d<f32>;
 
 struct Params {
     camera_pos: vec3f,
@@ -12,15 +33,60 @@ struct Params {
     accumulated_frames: u32,
     width: u32,
     height: u32,
+    sphere_count: u32,
+    triangle_mesh_count: u32,
+    sky: Sky,
+    // nonzero traces one wavelength per path instead of rgb
+    spectral: u32,
+    light_count: u32,
+    // summed power of all lights
+    light_power: f32,
+    // nonzero lights the scene with the environment map instead of the sky and sun
+    environment: u32,
+    // photographic stops every pixel is brightened by
+    exposure: f32,
+    // where random numbers come from, one of the SAMPLER_ constants
+    sampler_kind: u32,
+};
+
+struct Sky {
+    ground_color: vec3f,
+    sun_intensity: f32,
+    horizon_color: vec3f,
+    sun_focus: f32,
+    zenith_color: vec3f,
+    environment_intensity: f32,
+    sun_color: vec3f,
+    // radians the environment map is turned by around the y axis
+    environment_rotation: f32,
+    // nonzero replaces the gradient and the sun's lobe with the preetham daylight model for
+    // air this hazy, 2 being clear and 10 overcast
+    turbidity: f32,
+    // scales the preetham sky and sun, which are 1 for a white surface under the sun
+    daylight_intensity: f32,
+    // angular radius of the preetham sun's disk in radians
+    sun_radius: f32,
+    _pad: f32,
 };
 
 struct Material {
     diffuse_color: vec3f,
-    smoothness: f32,
+    roughness: f32,
     emission_color: vec3f,
     emission_strength: f32,
     refractive_index: f32,
-    flag: u32,
+    kind: u32,
+    flags: u32,
+    diffuse_texture: u32,
+    roughness_texture: u32,
+    emission_texture: u32,
+    normal_texture: u32,
+    metallic: f32,
+    absorption_color: vec3f,
+    absorption_density: f32,
+    sellmeier_b: vec3f,
+    cauchy_b: f32,
+    sellmeier_c: vec3f,
 };
 
 struct Sphere {
@@ -30,16 +96,79 @@ struct Sphere {
 };
 
 struct TriangleMesh {
-    _pad: vec2f,
-    start_index: u32,
-    vertex_count: u32,
-    aabb: Aabb,
+    bvh_root: u32,
+    base_vertex: u32,
+    first_index: u32,
+    index_count: u32,
+    flat_shading: u32,
     material: Material,
 };
 
-struct Aabb {
-    min: vec4f,
-    max: vec4f,
+struct Vertex {
+    position: vec3f,
+    u: f32,
+    normal: vec3f,
+    v: f32,
+    tangent: vec4f,
+};
+
+// a leaf when count > 0, otherwise children are left_first and left_first + 1
+struct BvhNode {
+    min: vec3f,
+    left_first: u32,
+    max: vec3f,
+    count: u32,
+};
+
+struct BrdfSample {
+    direction: vec3f,
+    // throughput divided by the probability of the direction, zero absorbs the path
+    weight: vec3f,
+    // solid angle density of the direction, for weighting it against light sampling
+    pdf: f32,
+};
+
+// the brdf times the cosine towards a direction, and the solid angle density with which
+// sample_brdf picks that direction
+struct BrdfEval {
+    value: vec3f,
+    pdf: f32,
+};
+
+struct Light {
+    // a sphere index, a mesh index offset by sphere_count, or ANALYTIC_LIGHT
+    object: u32,
+    // triangle within the mesh or index of the analytic light
+    triangle: u32,
+    probability: f32,
+    cdf: f32,
+};
+
+// intensity falls off with the squared distance from point and spot lights, and is constant
+// from directional ones
+struct AnalyticLight {
+    position: vec3f,
+    kind: u32,
+    // which way spot and directional lights shine
+    direction: vec3f,
+    cos_inner: f32,
+    intensity: vec3f,
+    // where a spot light has faded out, or a directional light's angular radius
+    cos_outer: f32,
+    // index of the profile shaping a point or spot light around its direction, or NO_PROFILE
+    profile: u32,
+};
+
+// direction and distance to a spot on a light, with the radiance it emits that way and the
+// solid angle density of having picked it. a zero pdf means nothing was sampled
+struct LightSample {
+    direction: vec3f,
+    distance: f32,
+    emitted: vec3f,
+    pdf: f32,
+    // from an analytic light, which brdf samples never hit, so emitted is its intensity and pdf
+    // only the chance of picking it
+    analytic: bool,
 };
 
 struct Ray {
@@ -50,67 +179,482 @@ struct Ray {
 struct RayHit {
     distance: f32,
     position: vec3f,
+    // faces back along the ray, whichever side was hit
     normal: vec3f,
+    uv: vec2f,
+    tangent: vec4f,
+    // the ray hit the surface from behind, i.e. from inside a closed object
     is_backface: bool,
+    // what was hit, numbered like Light
+    object: u32,
+    triangle: u32,
     material: Material,
     hit: bool,
 };
 
 const PI: f32 = 3.141592;
 
-const MAX_BOUNCES: u32 = 5u;
-const RAYS_PER_PIXEL: u32 = 10u;
+const NO_HIT: f32 = 1e30;
+// range of wavelengths in nm that spectral paths are traced at
+const WAVELENGTH_MIN: f32 = 380.0;
+const WAVELENGTH_MAX: f32 = 780.0;
+// wavelength in nm that dispersive materials are evaluated at outside spectral mode
+const D_LINE: f32 = 587.6;
+// cie xyz to linear srgb, balanced so a flat spectrum comes out white and scaled by the width
+// of the wavelength range to undo its sampling probability
+const XYZ_TO_RGB = mat3x3f(
+    10.0983, -3.8165, 0.2296,
+    -4.7902, 7.3888, -0.8408,
+    -1.5537, 0.1635, 4.3565,
+);
+// cie xyz to linear srgb with the d65 white point, for the preetham sky's colors
+const XYZ_TO_SRGB = mat3x3f(
+    3.2406, -0.9689, 0.0557,
+    -1.5372, 1.8758, -0.2040,
+    -0.4986, 0.0415, 1.0570,
+);
+// luminance in cd/m² of a unit of radiance in daylight, so that the sun overhead lights a white
+// surface to about 1, same as color::DAYLIGHT_UNIT
+const DAYLIGHT_UNIT: f32 = 1e5 / PI;
+// rec. 709 weights giving the luminance of a linear srgb color, same as color::LUMINANCE
+const LUMINANCE = vec3f(0.2126, 0.7152, 0.0722);
+// illuminance in lux of sunlight above the atmosphere
+const SOLAR_ILLUMINANCE: f32 = 128e3;
+
+// how far bounced rays start off the surface, so they don't hit it again
+const RAY_OFFSET: f32 = 1e-4;
+const NO_TEXTURE: u32 = 0xffffffffu;
 
-const GROUND_COLOR: vec3f = vec3f(0.35, 0.3, 0.35);
-const SKY_COLOR_HORIZON: vec3f = vec3f(1.0, 1.0, 1.0);
-const SKY_COLOR_ZENITH: vec3f = vec3f(0.08, 0.37, 0.73);
+// material kinds, see `MaterialKind`
+const DIFFUSE: u32 = 0u;
+const METAL: u32 = 1u;
+const DIELECTRIC: u32 = 2u;
+const EMISSIVE: u32 = 3u;
+// material flags
+const TWO_SIDED: u32 = 1u;
+// light kinds, see `LightKind`
+const POINT: u32 = 0u;
+const SPOT: u32 = 1u;
+const DIRECTIONAL: u32 = 2u;
+// Light.object of analytic lights
+const ANALYTIC_LIGHT: u32 = 0xffffffffu;
+const NO_PROFILE: u32 = 0xffffffffu;
+// matches ies::VERTICAL_SAMPLES and ies::HORIZONTAL_SAMPLES
+const PROFILE_VERTICAL_SAMPLES: u32 = 128u;
+const PROFILE_HORIZONTAL_SAMPLES: u32 = 32u;
 
-const SUN_INTENSITY: f32 = 10.0;
-const SUN_FOCUS: f32 = 500.0;
-const SUN_COLOR: vec3f = vec3f(1.0, 0.9, 0.6);
+// matches bvh::MAX_DEPTH
+const BVH_STACK_SIZE: u32 = 32u;
+
+const MAX_BOUNCES: u32 = 5u;
+
+// matches utils::SamplerKind
+const SAMPLER_RANDOM: u32 = 0u;
+const SAMPLER_SOBOL: u32 = 1u;
+const SAMPLER_BLUE_NOISE: u32 = 2u;
+const RAYS_PER_PIXEL: u32 = 10u;
 
 fn hash(seed: vec2f) -> u32 {
-    var h = u32(seed.x * 73856093.0) ^ u32(seed.y * 19349663.0);
+    return hash_u32(u32(seed.x * 73856093.0) ^ u32(seed.y * 19349663.0));
+}
+
+fn hash_u32(seed: u32) -> u32 {
+    var h = seed;
     h = (h ^ (h >> 16u)) * 0x45d9f3bu;
     h = (h ^ (h >> 16u)) * 0x45d9f3bu;
     h = h ^ (h >> 16u);
     return h;
 }
 
+// where a path gets its random numbers, see params.sampler_kind. every call for one or two
+// numbers moves on to the next dimension of the current sample
+struct Sampler {
+    kind: u32,
+    // running state of the random generator, or a hash of the pixel for the others
+    seed: u32,
+    pixel: vec2u,
+    // which sample of the pixel this is, counting every ray of every frame
+    index: u32,
+    dimension: u32,
+};
+
+// the sampler for the pixel's first ray this frame, see next_sample
+fn pixel_sampler(pixel: vec2u, frag_coord: vec2f) -> Sampler {
+    var seed = hash_u32(pixel.x ^ hash_u32(pixel.y));
+    if params.sampler_kind == SAMPLER_RANDOM {
+        seed = hash(frag_coord + params.random_seed);
+    }
+    return Sampler(params.sampler_kind, seed, pixel, params.accumulated_frames * RAYS_PER_PIXEL, 0u);
+}
+
+// moves on to the pixel's next ray. every ray starts at the first dimension, the camera's
+// jitter, with its bounces' dimensions after it
+fn next_sample(state: ptr<function, Sampler>) {
+    (*state).index += 1u;
+    (*state).dimension = 0u;
+}
+
 fn next_random(state: ptr<function, u32>) -> u32 {
     (*state) = (*state) * 1664525u + 1013904223u;
     let result = ((*state >> ((*state >> 28u) + 4u)) ^ *state) * 277803737u;
     return (result >> 22u) ^ result;
 }
 
-fn random_value(state: ptr<function, u32>) -> f32 {
-    return f32(next_random(state)) / 4294967295.0;
+// owen scrambling of the bits of x, as a hash of their reversal by laine and karras, from
+// burley 2020, "practical hash-based owen scrambling"
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    var v = reverseBits(x) + seed;
+    v ^= v * 0x6c50b47cu;
+    v ^= v * 0xb82f1e52u;
+    v ^= v * 0xc7afe638u;
+    v ^= v * 0x8d22f6e6u;
+    return reverseBits(v);
+}
+
+// the first two dimensions of the sobol sequence, as fractions of 2^32
+fn sobol(index: u32) -> vec2u {
+    var y = 0u;
+    var direction = 1u << 31u;
+    var bits = index;
+    while bits != 0u {
+        if (bits & 1u) != 0u {
+            y ^= direction;
+        }
+        bits >>= 1u;
+        direction ^= direction >> 1u;
+    }
+    return vec2u(reverseBits(index), y);
 }
 
-fn random_normal_distribution(state: ptr<function, u32>) -> f32 {
-    let theta = 2.0 * PI * random_value(state);
-    let rho = sqrt(-2.0 * log(random_value(state)));
-    return rho * cos(theta);
+// two dimensions of a sobol point, as fractions of 2^32. each dimension shuffles the
+// samples differently so they don't line up with the others, while every one of them
+// stays well spread on its own
+fn sobol_pair(state: Sampler) -> vec2u {
+    let seed = hash_u32(state.seed ^ hash_u32(state.dimension));
+    let point = sobol(nested_uniform_scramble(state.index, seed));
+    return vec2u(
+        nested_uniform_scramble(point.x, hash_u32(seed ^ 1u)),
+        nested_uniform_scramble(point.y, hash_u32(seed ^ 2u)),
+    );
 }
 
-fn random_direction(state: ptr<function, u32>) -> vec3f {
-    let x = random_normal_distribution(state);
-    let y = random_normal_distribution(state);
-    let z = random_normal_distribution(state);
-    return normalize(vec3f(x, y, z));
+// a value from the blue-noise tile, as a fraction of 2^32, moved around by the dimension
+// and salt so different numbers don't come from the same spot
+fn blue_noise(state: Sampler, salt: u32) -> u32 {
+    let size = textureDimensions(blue_noise_tile);
+    let offset = hash_u32(state.dimension ^ hash_u32(salt));
+    let texel = (state.pixel + vec2u(offset & 0xffffu, offset >> 16u)) % size;
+    return u32(textureLoad(blue_noise_tile, texel, 0).r * 16777216.0) << 8u;
 }
 
-fn get_environment_light(ray: Ray, light_dir: vec3f) -> vec3f {
-    let sky_gradient = mix(SKY_COLOR_HORIZON,
-        SKY_COLOR_ZENITH,
-        pow(smoothstep(0.0, 0.4, ray.direction.y), 0.35));
+// two dimensions of a blue-noise sample, as fractions of 2^32: the r2 sequence, which
+// spreads successive samples evenly, shifted by the tile
+fn blue_noise_pair(state: Sampler) -> vec2u {
+    return vec2u(
+        blue_noise(state, 0u) + state.index * 3242174889u,
+        blue_noise(state, 1u) + state.index * 2447445413u,
+    );
+}
 
-    let sun = pow(max(dot(ray.direction, light_dir), 0.0), SUN_FOCUS) * SUN_INTENSITY;
+// fraction of 2^32 to a float below 1
+fn unit_float(x: u32) -> f32 {
+    return f32(x >> 8u) / 16777216.0;
+}
+
+fn random_value(state: ptr<function, Sampler>) -> f32 {
+    var value: f32;
+    if (*state).kind == SAMPLER_SOBOL {
+        value = unit_float(sobol_pair(*state).x);
+    } else if (*state).kind == SAMPLER_BLUE_NOISE {
+        // the golden ratio spreads successive samples evenly in one dimension
+        value = unit_float(blue_noise(*state, 0u) + (*state).index * 2654435769u);
+    } else {
+        value = f32(next_random(&(*state).seed)) / 4294967295.0;
+    }
+    (*state).dimension += 1u;
+    return value;
+}
+
+// two numbers that, unlike two calls to random_value, are spread evenly together
+fn random_pair(state: ptr<function, Sampler>) -> vec2f {
+    var pair: vec2f;
+    if (*state).kind == SAMPLER_SOBOL {
+        let bits = sobol_pair(*state);
+        pair = vec2f(unit_float(bits.x), unit_float(bits.y));
+    } else if (*state).kind == SAMPLER_BLUE_NOISE {
+        let bits = blue_noise_pair(*state);
+        pair = vec2f(unit_float(bits.x), unit_float(bits.y));
+    } else {
+        let x = f32(next_random(&(*state).seed)) / 4294967295.0;
+        let y = f32(next_random(&(*state).seed)) / 4294967295.0;
+        pair = vec2f(x, y);
+    }
+    (*state).dimension += 1u;
+    return pair;
+}
+
+// uniformly distributed on the unit sphere
+fn random_direction(state: ptr<function, Sampler>) -> vec3f {
+    let u = random_pair(state);
+    let z = 1.0 - 2.0 * u.x;
+    let r = sqrt(max(1.0 - z * z, 0.0));
+    let phi = 2.0 * PI * u.y;
+    return vec3f(r * cos(phi), r * sin(phi), z);
+}
+
+// sky_weight scales the part that paths could also have sampled directly, see sky_light
+fn get_environment_light(ray: Ray, sky_weight: f32) -> vec3f {
+    let sky = params.sky;
+    let sky_gradient = mix(sky.horizon_color,
+        sky.zenith_color,
+        pow(smoothstep(0.0, 0.4, ray.direction.y), 0.35));
 
     let ground_to_sky = smoothstep(-0.01, 0.0, ray.direction.y);
-    let sun_mask = ground_to_sky >= 1.0;
+    var background = vec3f(0.0);
+    if params.environment == 0u && sky.turbidity > 0.0 {
+        // the ground reflects the horizon above it
+        let daylight = preetham_sky(ray.direction, params.light_dir, sky);
+        background = mix(sky.ground_color * daylight, daylight, ground_to_sky);
+    } else if params.environment == 0u {
+        background = mix(sky.ground_color, sky_gradient, ground_to_sky);
+    }
 
-    return mix(GROUND_COLOR, sky_gradient, ground_to_sky) + sun * SUN_COLOR * f32(u32(sun_mask));
+    return background + sky_light(ray.direction) * sky_weight;
+}
+
+// the part of the environment that light sampling aims at: the whole environment map if there
+// is one, otherwise the sun
+fn sky_light(direction: vec3f) -> vec3f {
+    if params.environment != 0u {
+        return environment_light(direction);
+    }
+    return sun_light(direction, params.light_dir, params.sky);
+}
+
+fn sun_light(direction: vec3f, light_dir: vec3f, sky: Sky) -> vec3f {
+    var sun = vec3f(0.0);
+    if sky.turbidity > 0.0 {
+        let one_minus_cos = sun_cone(sky);
+        let inside = 1.0 - dot(direction, light_dir) <= one_minus_cos;
+        // spreading the same light over a bigger disk only softens shadows
+        let radiance = SOLAR_ILLUMINANCE / DAYLIGHT_UNIT / (2.0 * PI * one_minus_cos);
+        sun = sun_transmittance(light_dir.y, sky.turbidity) * radiance * sky.daylight_intensity
+            * f32(u32(inside));
+    } else {
+        sun = pow(max(dot(direction, light_dir), 0.0), sky.sun_focus) * sky.sun_intensity
+            * sky.sun_color;
+    }
+    // hidden below the horizon
+    let sun_mask = smoothstep(-0.01, 0.0, direction.y) >= 1.0;
+    return sun * f32(u32(sun_mask));
+}
+
+// one minus the cosine of the preetham sun's angular radius, without cancelling to zero
+fn sun_cone(sky: Sky) -> f32 {
+    let s = sin(sky.sun_radius * 0.5);
+    return 2.0 * s * s;
+}
+
+// share of sunlight reaching the ground through rayleigh and aerosol scattering, from preetham
+// et al., "a practical analytic model for daylight" (1999), at the wavelengths of red, green
+// and blue
+fn sun_transmittance(cos_zenith_in: f32, turbidity: f32) -> vec3f {
+    let cos_zenith = clamp(cos_zenith_in, 0.0, 1.0);
+    let zenith = degrees(acos(cos_zenith));
+    // relative optical mass of the air the light passes through
+    let air_mass = 1.0 / (cos_zenith + 0.15 * pow(93.885 - zenith, -1.253));
+    let wavelength = vec3f(0.68, 0.55, 0.44);
+    let rayleigh = exp(-0.008735 * pow(wavelength, vec3f(-4.08)) * air_mass);
+    let beta = 0.04608 * turbidity - 0.04586;
+    let aerosol = exp(-beta * pow(wavelength, vec3f(-1.3)) * air_mass);
+    return rayleigh * aerosol;
+}
+
+// perez et al.'s sky distribution for luminance y and chromaticities x and y at once, the
+// coefficients a to e being per channel
+fn perez(cos_theta: f32, gamma: f32, coefficients: array<vec3f, 5>) -> vec3f {
+    let a = coefficients[0];
+    let b = coefficients[1];
+    let c = coefficients[2];
+    let d = coefficients[3];
+    let e = coefficients[4];
+    return (1.0 + a * exp(b / max(cos_theta, 1e-3)))
+        * (1.0 + c * exp(d * gamma) + e * cos(gamma) * cos(gamma));
+}
+
+// radiance of the clear sky towards a direction, from the preetham model's fit of luminance and
+// chromaticity, or towards the horizon when the direction is below it
+fn preetham_sky(direction: vec3f, light_dir: vec3f, sky: Sky) -> vec3f {
+    let t = sky.turbidity;
+    let coefficients = array<vec3f, 5>(
+        vec3f(0.1787, -0.0193, -0.0167) * t + vec3f(-1.4630, -0.2592, -0.2608),
+        vec3f(-0.3554, -0.0665, -0.0950) * t + vec3f(0.4275, 0.0008, 0.0092),
+        vec3f(-0.0227, -0.0004, -0.0079) * t + vec3f(5.3251, 0.2125, 0.2102),
+        vec3f(0.1206, -0.0641, -0.0441) * t + vec3f(-2.5771, -0.8989, -1.6537),
+        vec3f(-0.0670, -0.0033, -0.0109) * t + vec3f(0.3703, 0.0452, 0.0529),
+    );
+
+    // the model ends at sunset, so keep the sun there and fade out through twilight
+    let sun_theta = acos(clamp(light_dir.y, 0.0, 1.0));
+    let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * sun_theta);
+    let zenith_luminance = (4.0453 * t - 4.9710) * tan(chi) - 0.2155 * t + 2.4192;
+    let theta = vec3f(sun_theta * sun_theta * sun_theta, sun_theta * sun_theta, sun_theta);
+    let zenith_x = t * t * dot(theta, vec3f(0.00166, -0.00375, 0.00209))
+        + t * (dot(theta, vec3f(-0.02903, 0.06377, -0.03202)) + 0.00394)
+        + dot(theta, vec3f(0.11693, -0.21196, 0.06052))
+        + 0.25886;
+    let zenith_y = t * t * dot(theta, vec3f(0.00275, -0.00610, 0.00317))
+        + t * (dot(theta, vec3f(-0.04214, 0.08970, -0.04153)) + 0.00516)
+        + dot(theta, vec3f(0.15346, -0.26756, 0.06670))
+        + 0.26688;
+    let zenith = vec3f(zenith_luminance, zenith_x, zenith_y);
+
+    let gamma = acos(clamp(dot(direction, light_dir), -1.0, 1.0));
+    let luminance_xy = zenith * perez(direction.y, gamma, coefficients)
+        / perez(1.0, sun_theta, coefficients);
+    // kcd/m², fading through twilight
+    let luminance = max(luminance_xy.x, 0.0) * 1e3 / DAYLIGHT_UNIT
+        * smoothstep(-0.1, 0.0, light_dir.y)
+        * sky.daylight_intensity;
+    let x = luminance_xy.y;
+    let y = luminance_xy.z;
+    let xyz = vec3f(x / y, 1.0, (1.0 - x - y) / y) * luminance;
+    return max(XYZ_TO_SRGB * xyz, vec3f(0.0));
+}
+
+// share of light samples spent on the sky rather than on emissive objects
+fn sky_chance() -> f32 {
+    var lit = params.sky.sun_intensity > 0.0 && any(params.light_dir != vec3f(0.0));
+    if params.sky.turbidity > 0.0 {
+        lit = params.sky.daylight_intensity > 0.0 && any(params.light_dir != vec3f(0.0));
+    }
+    if params.environment != 0u {
+        lit = params.sky.environment_intensity > 0.0;
+    }
+    if !lit {
+        return 0.0;
+    }
+    return select(0.5, 1.0, params.light_count == 0u);
+}
+
+// solid angle density of light sampling picking a direction towards the sky. the sun's lobe
+// or disk is sampled exactly, environment maps texel by texel
+fn sky_pdf(direction: vec3f) -> f32 {
+    if params.environment != 0u {
+        return sky_chance() * environment_pdf(direction);
+    }
+    if params.sky.turbidity > 0.0 {
+        let one_minus_cos = sun_cone(params.sky);
+        let inside = 1.0 - dot(direction, params.light_dir) <= one_minus_cos;
+        return sky_chance() * f32(u32(inside)) / (2.0 * PI * one_minus_cos);
+    }
+    let focus = params.sky.sun_focus;
+    let cos = max(dot(direction, params.light_dir), 0.0);
+    return sky_chance() * (focus + 1.0) / (2.0 * PI) * pow(cos, focus);
+}
+
+// picks a direction towards the sky with the density of sky_pdf
+fn sample_sky(state: ptr<function, Sampler>) -> vec3f {
+    if params.environment != 0u {
+        return sample_environment(state);
+    }
+    let focus = params.sky.sun_focus;
+    let u = random_pair(state);
+    var cos_theta = 0.0;
+    if params.sky.turbidity > 0.0 {
+        cos_theta = 1.0 - u.x * sun_cone(params.sky);
+    } else {
+        cos_theta = pow(u.x, 1.0 / (focus + 1.0));
+    }
+    let sin_theta = sqrt(max(1.0 - cos_theta * cos_theta, 0.0));
+    let phi = 2.0 * PI * u.y;
+    let basis = orthonormal_basis(params.light_dir);
+    return (basis[0] * cos(phi) + basis[1] * sin(phi)) * sin_theta + params.light_dir * cos_theta;
+}
+
+// radiance of the environment map's texel towards a direction
+fn environment_light(direction: vec3f) -> vec3f {
+    let texel = environment_texel(direction);
+    return textureLoad(environment, texel, 0).rgb * params.sky.environment_intensity;
+}
+
+// the texel of the environment map, turned by the sky's rotation, towards a direction
+fn environment_texel(direction: vec3f) -> vec2i {
+    let size = vec2f(textureDimensions(environment));
+    let uv = sphere_uv(rotate_y(direction, -params.sky.environment_rotation));
+    return vec2i(min(uv * size, size - 1.0));
+}
+
+// density of sample_environment picking a direction: its texel's share of the map, spread
+// over the solid angle the texel covers
+fn environment_pdf(direction: vec3f) -> f32 {
+    let sin_theta = sqrt(max(1.0 - direction.y * direction.y, 0.0));
+    if sin_theta <= 0.0 {
+        return 0.0;
+    }
+    let size = textureDimensions(environment);
+    let texel = environment_texel(direction);
+    let probability = cdf_share(texel.y, i32(size.y)) * cdf_share(texel.x, texel.y);
+    return probability * f32(size.x * size.y) / (2.0 * PI * PI * sin_theta);
+}
+
+// picks a row and then a texel in it by their share of the map's weight, and a direction
+// uniformly within the texel's latitude and longitude
+fn sample_environment(state: ptr<function, Sampler>) -> vec3f {
+    let size = textureDimensions(environment);
+    let random = random_pair(state);
+    let row = sample_cdf(i32(size.y), i32(size.y), random.x);
+    let column = sample_cdf(i32(size.x), i32(row.x), random.y);
+    // the inverse of sphere_uv
+    let theta = PI * (row.x + row.y) / f32(size.y);
+    let phi = 2.0 * PI * (0.5 - (column.x + column.y) / f32(size.x));
+    let direction = vec3f(sin(theta) * cos(phi), cos(theta), sin(theta) * sin(phi));
+    return rotate_y(direction, params.sky.environment_rotation);
+}
+
+// index of the entry in a row of environment_cdf that u falls in, and how far through its
+// share it lies
+fn sample_cdf(count: i32, row: i32, u: f32) -> vec2f {
+    var lo = 0;
+    var hi = count - 1;
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+        if u < textureLoad(environment_cdf, vec2i(mid, row), 0).r {
+            hi = mid;
+        } else {
+            lo = mid + 1;
+        }
+    }
+    let share = cdf_share(lo, row);
+    let below = textureLoad(environment_cdf, vec2i(lo, row), 0).r - share;
+    var t = 0.5;
+    if share > 0.0 {
+        t = clamp((u - below) / share, 0.0, 1.0);
+    }
+    return vec2f(f32(lo), t);
+}
+
+// probability of an entry in a row of environment_cdf
+fn cdf_share(index: i32, row: i32) -> f32 {
+    var below = 0.0;
+    if index > 0 {
+        below = textureLoad(environment_cdf, vec2i(index - 1, row), 0).r;
+    }
+    return textureLoad(environment_cdf, vec2i(index, row), 0).r - below;
+}
+
+// turns v by an angle in radians around the y axis
+fn rotate_y(v: vec3f, angle: f32) -> vec3f {
+    let c = cos(angle);
+    let s = sin(angle);
+    return vec3f(v.x * c + v.z * s, v.y, v.z * c - v.x * s);
+}
+
+// power heuristic weight of a sample from the strategy with pdf, against one other strategy
+// that could have produced it with other_pdf
+fn mis_weight(pdf: f32, other_pdf: f32) -> f32 {
+    let ratio = other_pdf / pdf;
+    return 1.0 / (1.0 + ratio * ratio);
 }
 
 fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
@@ -118,104 +662,668 @@ fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
     return t * t * (3.0 - t * 2.0);
 }
 
-fn trace(ray: ptr<function, Ray>, state: ptr<function, u32>) -> vec3f {
+// averages the paths of a pixel's rays this frame, each jittered away from camera_ray by
+// its own sample
+fn trace(camera_ray: ptr<function, Ray>, state: ptr<function, Sampler>) -> vec3f {
     var total_light = vec3f(0.0);
     for (var i = 0u; i < RAYS_PER_PIXEL; i = i + 1u) {
-        var r = *ray;
+        let direction = normalize((*camera_ray).direction + random_direction(state) * 0.001);
+        var r = Ray((*camera_ray).origin, direction);
         total_light = total_light + trace_single(&r, state);
+        next_sample(state);
     }
 
     return total_light / f32(RAYS_PER_PIXEL);
 }
 
-fn trace_single(ray: ptr<function, Ray>, state: ptr<function, u32>) -> vec3f {
+fn trace_single(ray: ptr<function, Ray>, state: ptr<function, Sampler>) -> vec3f {
     var light = vec3f(0.0, 0.0, 0.0);
     var color = vec3f(1.0, 1.0, 1.0);
+    // density of the last bounce, to weigh what it hits against light sampling. zero for
+    // camera rays and dielectric bounces, which light sampling can't reproduce
+    var last_pdf = 0.0;
+    var wavelength = 0.0;
+    if params.spectral != 0u {
+        wavelength = WAVELENGTH_MIN + (WAVELENGTH_MAX - WAVELENGTH_MIN) * random_value(state);
+    }
 
     for (var bounce: u32 = 0u; bounce < MAX_BOUNCES; bounce = bounce + 1u) {
-        let hit = calculate_collision(*ray);
+        var hit = calculate_collision(*ray);
         if hit.hit {
-            (*ray).origin = hit.position;
-            let diffuse = normalize(hit.normal + random_direction(state));
-            let specular = reflect((*ray).direction, hit.normal, 0.0);
-
-            if hit.material.flag == 1u {
-                if hit.is_backface {
-                    let refracted = refract((*ray).direction, hit.normal, hit.material.refractive_index);
-                    let kr = pow(1.0 - max(dot(-(*ray).direction, hit.normal), 0.0), 5.0);
-                    (*ray).direction = normalize(mix(refracted, specular, kr));
+            hit.material = surface_material(hit.material, hit.uv);
+            hit.normal = normal_mapped(hit.normal, hit.tangent, hit.material.normal_texture, hit.uv);
+            if hit.is_backface && hit.material.kind == DIELECTRIC {
+                color = color * at_wavelength(absorption(hit.material, hit.distance), wavelength);
+            }
+            let emitted = hit.material.emission_color * hit.material.emission_strength;
+            if any(emitted != vec3f(0.0)) {
+                var weight = 1.0;
+                if last_pdf > 0.0 {
+                    weight = mis_weight(last_pdf, light_pdf((*ray).origin, (*ray).direction, hit));
                 }
-            } else {
-                (*ray).direction = mix(diffuse, specular, hit.material.smoothness);
+                light = light + color * at_wavelength(emitted, wavelength) * weight;
             }
 
-            let emitted = hit.material.emission_color * hit.material.emission_strength;
-            color = color * hit.material.diffuse_color;
-            light = light + color * emitted;
+            if hit.material.kind == EMISSIVE {
+                break;
+            } else if hit.material.kind == DIELECTRIC {
+                // the outside is assumed to be air
+                let index = refractive_index(hit.material, wavelength);
+                let eta = select(1.0 / index, index, hit.is_backface);
+                let cos_i = clamp(dot(-(*ray).direction, hit.normal), 0.0, 1.0);
+                if random_value(state) < fresnel_dielectric(cos_i, eta) {
+                    (*ray).direction = reflect((*ray).direction, hit.normal, 0.0);
+                } else {
+                    (*ray).direction = refract((*ray).direction, hit.normal, eta);
+                }
+                last_pdf = 0.0;
+            } else {
+                if params.light_count > 0u || sky_chance() > 0.0 {
+                    let light_sample = sample_light(hit.position, state);
+                    let brdf = eval_brdf((*ray).direction, hit.normal, hit.material, light_sample.direction);
+                    let weight = select(mis_weight(light_sample.pdf, brdf.pdf), 1.0, light_sample.analytic);
+                    if light_sample.pdf > 0.0 && brdf.pdf > 0.0 && unoccluded(hit.position, hit.normal, light_sample) {
+                        light = light + color * at_wavelength(brdf.value, wavelength)
+                            * at_wavelength(light_sample.emitted, wavelength)
+                            * (weight / light_sample.pdf);
+                    }
+                }
+
+                let sample = sample_brdf((*ray).direction, hit.normal, hit.material, state);
+                if all(sample.weight == vec3f(0.0)) {
+                    break;
+                }
+                (*ray).direction = sample.direction;
+                color = color * at_wavelength(sample.weight, wavelength);
+                last_pdf = sample.pdf;
+            }
+            // start on the side of the surface the ray leaves from
+            let side = select(-1.0, 1.0, dot((*ray).direction, hit.normal) >= 0.0);
+            (*ray).origin = hit.position + hit.normal * RAY_OFFSET * side;
         } else {
-            light = light + get_environment_light(*ray, params.light_dir) * color;
+            var sky_weight = 1.0;
+            if last_pdf > 0.0 {
+                sky_weight = mis_weight(last_pdf, sky_pdf((*ray).direction));
+            }
+            let environment_light = get_environment_light(*ray, sky_weight);
+            light = light + at_wavelength(environment_light, wavelength) * color;
             break;
         }
     }
 
+    if wavelength > 0.0 {
+        return wavelength_to_rgb(wavelength) * light.x;
+    }
     return light;
 }
 
+// applies the material's textures at the hit's uv
+fn surface_material(material: Material, uv: vec2f) -> Material {
+    var m = material;
+    if m.diffuse_texture != NO_TEXTURE {
+        m.diffuse_color = m.diffuse_color * srgb_to_linear(sample_texture(m.diffuse_texture, uv).rgb);
+    }
+    if m.roughness_texture != NO_TEXTURE {
+        let texel = sample_texture(m.roughness_texture, uv);
+        m.roughness = m.roughness * texel.g;
+        m.metallic = m.metallic * texel.b;
+    }
+    if m.emission_texture != NO_TEXTURE {
+        m.emission_color = m.emission_color * srgb_to_linear(sample_texture(m.emission_texture, uv).rgb);
+    }
+    return m;
+}
+
+// bends the normal by a tangent-space normal map, if there's one and a tangent to orient it
+fn normal_mapped(normal: vec3f, tangent: vec4f, normal_texture: u32, uv: vec2f) -> vec3f {
+    let t = tangent.xyz - normal * dot(normal, tangent.xyz);
+    if normal_texture == NO_TEXTURE || dot(t, t) < 1e-12 {
+        return normal;
+    }
+    let tangent_dir = normalize(t);
+    let bitangent = cross(normal, tangent_dir) * sign(tangent.w);
+    let m = sample_texture(normal_texture, uv).rgb * 2.0 - 1.0;
+    return normalize(tangent_dir * m.x + bitangent * m.y + normal * m.z);
+}
+
+fn sample_texture(index: u32, uv: vec2f) -> vec4f {
+    return textureSampleLevel(textures, texture_sampler, uv, index, 0.0);
+}
+
+fn srgb_to_linear(c: vec3f) -> vec3f {
+    return select(pow((c + 0.055) / 1.055, vec3f(2.4)), c / 12.92, c <= vec3f(0.04045));
+}
+
 fn reflect(I: vec3f, N: vec3f, index: f32) -> vec3f {
     return I - 2.0 * dot(N, I) * N;
 }
 
-fn refract(I: vec3f, N: vec3f, index: f32) -> vec3f {
-    var cosi = clamp(dot(I, N), -1.0, 1.0);
-    let etai = 1.0;
-    let etat = index;
-    var n = N;
-    var eta = etai / etat;
-    if cosi < 0.0 {
-        cosi = -cosi;
-    } else {
-        n = -N;
-    }
-    let k = 1.0 - eta * eta * (1.0 - cosi * cosi);
+// bends i through a surface whose normal n faces it, eta being the ratio of the refractive
+// indices on the incoming and outgoing side. returns zero on total internal reflection
+fn refract(i: vec3f, n: vec3f, eta: f32) -> vec3f {
+    let cos_i = clamp(dot(-i, n), 0.0, 1.0);
+    let k = 1.0 - eta * eta * (1.0 - cos_i * cos_i);
     if k < 0.0 {
         return vec3f(0.0, 0.0, 0.0);
+    }
+    return eta * i + (eta * cos_i - sqrt(k)) * n;
+}
+
+// beer-lambert transmittance of distance travelled inside a dielectric
+fn absorption(material: Material, distance: f32) -> vec3f {
+    return pow(max(material.absorption_color, vec3f(1e-6)), vec3f(material.absorption_density * distance));
+}
+
+// refractive index of a dielectric at a wavelength in nm, or at the d line for 0
+fn refractive_index(material: Material, wavelength: f32) -> f32 {
+    let um = select(D_LINE, wavelength, wavelength > 0.0) / 1000.0;
+    let um2 = um * um;
+    if any(material.sellmeier_b != vec3f(0.0)) {
+        let terms = material.sellmeier_b * um2 / (um2 - material.sellmeier_c);
+        return sqrt(1.0 + terms.x + terms.y + terms.z);
+    }
+    return material.refractive_index + material.cauchy_b / um2;
+}
+
+// value at a wavelength of a smooth spectrum whose blue, green and red parts sum to one, so
+// white stays flat and reflectances stay within 0..1
+fn rgb_to_spectrum(rgb: vec3f, wavelength: f32) -> f32 {
+    let blue = 1.0 - smoothstep(470.0, 520.0, wavelength);
+    let red = smoothstep(560.0, 610.0, wavelength);
+    return rgb.r * red + rgb.g * (1.0 - red - blue) + rgb.b * blue;
+}
+
+// in spectral mode, the color's spectrum at the path's wavelength in every channel. rgb paths,
+// with a wavelength of 0, keep the color
+fn at_wavelength(rgb: vec3f, wavelength: f32) -> vec3f {
+    if wavelength > 0.0 {
+        return vec3f(rgb_to_spectrum(rgb, wavelength));
+    }
+    return rgb;
+}
+
+fn cie_lobe(wavelength: f32, mu: f32, below: f32, above: f32) -> f32 {
+    let t = (wavelength - mu) / select(above, below, wavelength < mu);
+    return exp(-0.5 * t * t);
+}
+
+// cie 1931 color matching functions, fitted by wyman et al. 2013
+fn cie_xyz(wavelength: f32) -> vec3f {
+    return vec3f(
+        1.056 * cie_lobe(wavelength, 599.8, 37.9, 31.0) + 0.362 * cie_lobe(wavelength, 442.0, 16.0, 26.7)
+            - 0.065 * cie_lobe(wavelength, 501.1, 20.4, 26.2),
+        0.821 * cie_lobe(wavelength, 568.8, 46.9, 40.5) + 0.286 * cie_lobe(wavelength, 530.9, 16.3, 31.1),
+        1.217 * cie_lobe(wavelength, 437.0, 11.8, 36.0) + 0.681 * cie_lobe(wavelength, 459.0, 26.0, 13.8),
+    );
+}
+
+// linear srgb that a unit of radiance at a wavelength contributes to a pixel
+fn wavelength_to_rgb(wavelength: f32) -> vec3f {
+    return XYZ_TO_RGB * cie_xyz(wavelength);
+}
+
+// exact unpolarized fresnel reflectance of a dielectric boundary, 1 on total internal reflection
+fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = sqrt(1.0 - sin2_t);
+    let rs = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let rp = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    return 0.5 * (rs * rs + rp * rp);
+}
+
+// two tangents completing n to an orthonormal basis (Duff et al. 2017)
+fn orthonormal_basis(n: vec3f) -> mat2x3f {
+    let s = select(-1.0, 1.0, n.z >= 0.0);
+    let a = -1.0 / (s + n.z);
+    let b = n.x * n.y * a;
+    return mat2x3f(
+        vec3f(1.0 + s * n.x * n.x * a, s * b, -s * n.x),
+        vec3f(b, s + n.y * n.y * a, -n.y),
+    );
+}
+
+fn fresnel_schlick(f0: vec3f, cos_theta: f32) -> vec3f {
+    return f0 + (1.0 - f0) * pow(1.0 - clamp(cos_theta, 0.0, 1.0), 5.0);
+}
+
+// smith masking for one direction, alpha2 being the squared ggx alpha
+fn smith_g1(n_dot_x: f32, alpha2: f32) -> f32 {
+    return 2.0 * n_dot_x / (n_dot_x + sqrt(alpha2 + (1.0 - alpha2) * n_dot_x * n_dot_x));
+}
+
+// height-correlated smith masking-shadowing
+fn smith_g2(n_dot_l: f32, n_dot_v: f32, alpha2: f32) -> f32 {
+    let l = n_dot_v * sqrt(alpha2 + (1.0 - alpha2) * n_dot_l * n_dot_l);
+    let v = n_dot_l * sqrt(alpha2 + (1.0 - alpha2) * n_dot_v * n_dot_v);
+    return 2.0 * n_dot_l * n_dot_v / (l + v);
+}
+
+// samples a microfacet normal from the ggx distribution of normals visible from v, both in
+// the local frame where the surface normal is +z (Heitz 2018)
+fn sample_ggx_vndf(v: vec3f, alpha: f32, u1: f32, u2: f32) -> vec3f {
+    let vh = normalize(vec3f(alpha * v.x, alpha * v.y, v.z));
+    let len2 = vh.x * vh.x + vh.y * vh.y;
+    var t1 = vec3f(1.0, 0.0, 0.0);
+    if len2 > 0.0 {
+        t1 = vec3f(-vh.y, vh.x, 0.0) / sqrt(len2);
+    }
+    let t2 = cross(vh, t1);
+    let r = sqrt(u1);
+    let phi = 2.0 * PI * u2;
+    let p1 = r * cos(phi);
+    let s = 0.5 * (1.0 + vh.z);
+    let p2 = (1.0 - s) * sqrt(1.0 - p1 * p1) + s * r * sin(phi);
+    let nh = p1 * t1 + p2 * t2 + sqrt(max(1.0 - p1 * p1 - p2 * p2, 0.0)) * vh;
+    return normalize(vec3f(alpha * nh.x, alpha * nh.y, max(nh.z, 0.0)));
+}
+
+// chance of sampling the specular lobe rather than the diffuse one, by how much each would
+// reflect head-on to the viewer
+fn specular_chance(f0: vec3f, albedo: vec3f, n_dot_v: f32) -> f32 {
+    let fresnel = fresnel_schlick(f0, n_dot_v);
+    let specular_weight = dot(fresnel, vec3f(1.0));
+    let diffuse_weight = dot(albedo * (1.0 - fresnel), vec3f(1.0));
+    if specular_weight + diffuse_weight > 0.0 {
+        return specular_weight / (specular_weight + diffuse_weight);
+    }
+    return 1.0;
+}
+
+// samples the ggx specular lobe or the lambertian base under it, picking between them by
+// their estimated share of the reflected energy, and weights the direction by both
+fn sample_brdf(direction: vec3f, normal: vec3f, material: Material, state: ptr<function, Sampler>) -> BrdfSample {
+    var sample: BrdfSample;
+    let alpha = max(material.roughness * material.roughness, 1e-4);
+    let v = -direction;
+    let n_dot_v = max(dot(normal, v), 1e-4);
+    let colors = brdf_colors(material);
+
+    var l: vec3f;
+    if random_value(state) < specular_chance(colors[0], colors[1], n_dot_v) {
+        let basis = orthonormal_basis(normal);
+        let v_local = vec3f(dot(v, basis[0]), dot(v, basis[1]), n_dot_v);
+        let u = random_pair(state);
+        let h_local = sample_ggx_vndf(v_local, alpha, u.x, u.y);
+        l = reflect(direction, basis[0] * h_local.x + basis[1] * h_local.y + normal * h_local.z, 0.0);
     } else {
-        return eta * I + (eta * cosi - sqrt(k)) * n;
+        // the rare direction straight back into the surface cancels out
+        let sum = normal + random_direction(state);
+        l = select(normal, normalize(sum), dot(sum, sum) > 1e-12);
     }
+    let eval = eval_brdf(direction, normal, material, l);
+    if eval.pdf <= 0.0 {
+        return sample;
+    }
+    sample.direction = l;
+    sample.weight = eval.value / eval.pdf;
+    sample.pdf = eval.pdf;
+    return sample;
 }
 
+// ggx specular plus lambertian diffuse, seen along direction and lit from l. metals have no
+// diffuse base, and their specular color is diffuse_color
+fn eval_brdf(direction: vec3f, normal: vec3f, material: Material, l: vec3f) -> BrdfEval {
+    var eval: BrdfEval;
+    let v = -direction;
+    let half = v + l;
+    let n_dot_l = dot(normal, l);
+    if n_dot_l <= 0.0 || dot(half, half) < 1e-12 {
+        return eval;
+    }
+    let h = normalize(half);
+    let alpha = max(material.roughness * material.roughness, 1e-4);
+    let alpha2 = alpha * alpha;
+    let n_dot_v = max(dot(normal, v), 1e-4);
+    let n_dot_h = max(dot(normal, h), 0.0);
+    let colors = brdf_colors(material);
+    let f0 = colors[0];
+    let specular_chance = specular_chance(f0, colors[1], n_dot_v);
+
+    // written so it doesn't cancel to zero for mirror-like surfaces
+    let d = alpha2 * n_dot_h * n_dot_h + (1.0 - n_dot_h * n_dot_h);
+    let distribution = alpha2 / (PI * d * d);
+    let specular = fresnel_schlick(f0, dot(v, h)) * distribution * smith_g2(n_dot_l, n_dot_v, alpha2) / (4.0 * n_dot_v);
+    let diffuse = colors[1] * (1.0 - fresnel_schlick(f0, n_dot_v)) * n_dot_l / PI;
+    // visible normals are reflected, and the diffuse lobe is cosine weighted
+    let specular_pdf = smith_g1(n_dot_v, alpha2) * distribution / (4.0 * n_dot_v);
+    let diffuse_pdf = n_dot_l / PI;
+    eval.value = specular + diffuse;
+    eval.pdf = specular_chance * specular_pdf + (1.0 - specular_chance) * diffuse_pdf;
+    return eval;
+}
+
+// reflectance at normal incidence and diffuse albedo of a ggx material. metals have no
+// diffuse base, and their specular color is diffuse_color
+fn brdf_colors(material: Material) -> mat2x3f {
+    let metallic = select(material.metallic, 1.0, material.kind == METAL);
+    return mat2x3f(
+        mix(vec3f(0.04), material.diffuse_color, metallic),
+        material.diffuse_color * (1.0 - metallic),
+    );
+}
+
+// picks the sky by sky_chance and a direction towards it, or else a light by its share of
+// the emitted power and a point on it: uniformly in the cone a sphere covers, or uniformly by
+// area on a triangle
+fn sample_light(position: vec3f, state: ptr<function, Sampler>) -> LightSample {
+    var sample: LightSample;
+    let sky_chance = sky_chance();
+    let pick = random_value(state);
+    // pick can round up to 1, so don't rely on it to pick the sky when there's nothing else
+    if pick < sky_chance || params.light_count == 0u {
+        let direction = sample_sky(state);
+        sample.direction = direction;
+        sample.distance = NO_HIT;
+        sample.emitted = sky_light(direction);
+        sample.pdf = sky_pdf(direction);
+        return sample;
+    }
+    // reuse the same number to pick among the lights
+    let u = (pick - sky_chance) / (1.0 - sky_chance);
+    var lo = 0u;
+    var hi = params.light_count - 1u;
+    while lo < hi {
+        let mid = (lo + hi) / 2u;
+        if u < lights[mid].cdf {
+            hi = mid;
+        } else {
+            lo = mid + 1u;
+        }
+    }
+    let light = lights[lo];
+    let point = random_pair(state);
+    let u1 = point.x;
+    let u2 = point.y;
+
+    if light.object == ANALYTIC_LIGHT {
+        let analytic = analytic_lights[light.triangle];
+        return sample_analytic_light(position, analytic, (1.0 - sky_chance) * light.probability, u1, u2);
+    } else if light.object < params.sphere_count {
+        let sphere = spheres[light.object];
+        let to_center = sphere.position - position;
+        let d2 = dot(to_center, to_center);
+        let r2 = sphere.radius * sphere.radius;
+        if d2 <= r2 {
+            return sample;
+        }
+        let sin2_max = r2 / d2;
+        let cos_max = sqrt(max(1.0 - sin2_max, 0.0));
+        // 1 - cos_max without cancellation for small or distant spheres
+        let cone = sin2_max / (1.0 + cos_max);
+        let cos_theta = 1.0 - u1 * cone;
+        let sin_theta = sqrt(max(1.0 - cos_theta * cos_theta, 0.0));
+        let phi = 2.0 * PI * u2;
+        let w = to_center / sqrt(d2);
+        let basis = orthonormal_basis(w);
+        let direction = (basis[0] * cos(phi) + basis[1] * sin(phi)) * sin_theta + w * cos_theta;
+        let hit = sphere_intersect(Ray(position, direction), sphere);
+        if !hit.hit {
+            return sample;
+        }
+        let material = surface_material(sphere.material, hit.uv);
+        sample.direction = direction;
+        sample.distance = hit.distance;
+        sample.emitted = material.emission_color * material.emission_strength;
+        sample.pdf = (1.0 - sky_chance) * light.probability / (2.0 * PI * cone);
+    } else {
+        let mesh = triangle_meshes[light.object - params.sphere_count];
+        let first = mesh.first_index + light.triangle * 3u;
+        let v0 = triangle_vertices[mesh.base_vertex + triangle_indices[first]];
+        let v1 = triangle_vertices[mesh.base_vertex + triangle_indices[first + 1u]];
+        let v2 = triangle_vertices[mesh.base_vertex + triangle_indices[first + 2u]];
+        let su = sqrt(u1);
+        let w0 = 1.0 - su;
+        let w1 = u2 * su;
+        let w2 = 1.0 - w0 - w1;
+        let point = v0.position * w0 + v1.position * w1 + v2.position * w2;
+        let face = cross(v1.position - v0.position, v2.position - v0.position);
+        let area = 0.5 * length(face);
+        let to_light = point - position;
+        let distance = length(to_light);
+        if area <= 0.0 || distance <= 0.0 {
+            return sample;
+        }
+        let direction = to_light / distance;
+        // one-sided lights only shine from their counter-clockwise side
+        var cos_light = -dot(normalize(face), direction);
+        if (mesh.material.flags & TWO_SIDED) != 0u {
+            cos_light = abs(cos_light);
+        }
+        if cos_light <= 1e-6 {
+            return sample;
+        }
+        let uv = vec2f(v0.u, v0.v) * w0 + vec2f(v1.u, v1.v) * w1 + vec2f(v2.u, v2.v) * w2;
+        let material = surface_material(mesh.material, uv);
+        sample.direction = direction;
+        sample.distance = distance;
+        sample.emitted = material.emission_color * material.emission_strength;
+        sample.pdf = (1.0 - sky_chance) * light.probability * distance * distance / (area * cos_light);
+    }
+    return sample;
+}
+
+// relative intensity of a profile towards a direction, interpolated between its samples.
+// vertical angles are measured from the axis, horizontal ones around it from the first vector
+// of orthonormal_basis
+fn profile_intensity(profile: u32, direction: vec3f, axis: vec3f) -> f32 {
+    let basis = orthonormal_basis(axis);
+    let vertical = acos(clamp(dot(direction, axis), -1.0, 1.0));
+    let horizontal = atan2(dot(direction, basis[1]), dot(direction, basis[0]));
+    let size = vec2f(f32(PROFILE_VERTICAL_SAMPLES), f32(PROFILE_HORIZONTAL_SAMPLES));
+    let x = vertical / PI * (size.x - 1.0);
+    let turn = horizontal / (2.0 * PI);
+    let y = (turn - floor(turn)) * size.y;
+    let x0 = floor(x);
+    let y0 = floor(y);
+    let t = vec2f(x - x0, y - y0);
+    // vertical angles stop at the poles, horizontal ones go around
+    let columns = vec2i(i32(min(x0, size.x - 1.0)), i32(min(x0 + 1.0, size.x - 1.0)));
+    let first_row = i32(profile * PROFILE_HORIZONTAL_SAMPLES);
+    let rows = vec2i(i32(y0) % i32(size.y), i32(y0 + 1.0) % i32(size.y)) + first_row;
+    let near = mix(textureLoad(profiles, vec2i(columns.x, rows.x), 0).r,
+        textureLoad(profiles, vec2i(columns.y, rows.x), 0).r, t.x);
+    let far = mix(textureLoad(profiles, vec2i(columns.x, rows.y), 0).r,
+        textureLoad(profiles, vec2i(columns.y, rows.y), 0).r, t.x);
+    return mix(near, far, t.y);
+}
+
+// the direction towards a point or spot light, or one uniformly within a directional light's
+// angular radius, picked with the given probability
+fn sample_analytic_light(position: vec3f, light: AnalyticLight, probability: f32, u1: f32, u2: f32) -> LightSample {
+    var sample: LightSample;
+    sample.analytic = true;
+    sample.pdf = probability;
+    if light.kind == DIRECTIONAL {
+        let cos_theta = 1.0 - u1 * (1.0 - light.cos_outer);
+        let sin_theta = sqrt(max(1.0 - cos_theta * cos_theta, 0.0));
+        let phi = 2.0 * PI * u2;
+        let w = -light.direction;
+        let basis = orthonormal_basis(w);
+        sample.direction = (basis[0] * cos(phi) + basis[1] * sin(phi)) * sin_theta + w * cos_theta;
+        sample.distance = NO_HIT;
+        sample.emitted = light.intensity;
+        return sample;
+    }
+    let to_light = light.position - position;
+    let distance = length(to_light);
+    if distance <= 0.0 {
+        sample.pdf = 0.0;
+        return sample;
+    }
+    let direction = to_light / distance;
+    var falloff = 1.0;
+    if light.kind == SPOT {
+        let cos_angle = dot(-direction, light.direction);
+        let cos_inner = max(light.cos_inner, light.cos_outer + 1e-4);
+        falloff = smoothstep(light.cos_outer, cos_inner, cos_angle);
+    }
+    if light.profile != NO_PROFILE {
+        falloff = falloff * profile_intensity(light.profile, -direction, light.direction);
+    }
+    sample.direction = direction;
+    sample.distance = distance;
+    sample.emitted = light.intensity * falloff / (distance * distance);
+    return sample;
+}
+
+// solid angle density of light sampling from origin picking the point where hit, along
+// direction, landed on an emissive object
+fn light_pdf(origin: vec3f, direction: vec3f, hit: RayHit) -> f32 {
+    if params.light_power <= 0.0 {
+        return 0.0;
+    }
+    let chance = 1.0 - sky_chance();
+
+    if hit.object < params.sphere_count {
+        let sphere = spheres[hit.object];
+        let to_center = sphere.position - origin;
+        let d2 = dot(to_center, to_center);
+        let r2 = sphere.radius * sphere.radius;
+        if d2 <= r2 {
+            return 0.0;
+        }
+        let sin2_max = r2 / d2;
+        let cone = sin2_max / (1.0 + sqrt(max(1.0 - sin2_max, 0.0)));
+        let area = 4.0 * PI * r2;
+        let probability = emitted_power(sphere.material) * area / params.light_power;
+        return chance * probability / (2.0 * PI * cone);
+    }
+    let mesh = triangle_meshes[hit.object - params.sphere_count];
+    let first = mesh.first_index + hit.triangle * 3u;
+    let v0 = triangle_vertices[mesh.base_vertex + triangle_indices[first]].position;
+    let v1 = triangle_vertices[mesh.base_vertex + triangle_indices[first + 1u]].position;
+    let v2 = triangle_vertices[mesh.base_vertex + triangle_indices[first + 2u]].position;
+    let face = cross(v1 - v0, v2 - v0);
+    if dot(face, face) <= 0.0 {
+        return 0.0;
+    }
+    let cos_light = abs(dot(normalize(face), direction));
+    if cos_light <= 1e-6 {
+        return 0.0;
+    }
+    // the triangle's area cancels out of its probability and its density over it
+    return chance * emitted_power(mesh.material) * hit.distance * hit.distance / (params.light_power * cos_light);
+}
+
+// power emitted per unit area by luminance, see lights::power
+fn emitted_power(material: Material) -> f32 {
+    return material.emission_strength * dot(material.emission_color, LUMINANCE);
+}
+
+// whether nothing blocks the segment from a surface towards a sampled light
+fn unoccluded(position: vec3f, normal: vec3f, sample: LightSample) -> bool {
+    let shadow = calculate_collision(Ray(position + normal * RAY_OFFSET, sample.direction));
+    return !shadow.hit || shadow.distance >= sample.distance * 0.999 - RAY_OFFSET;
+}
+
+// walks the top-level tree, whose root is bvh node 0, down to the objects in its leaves
 fn calculate_collision(ray: Ray) -> RayHit {
     var closest_hit: RayHit;
-    for (var i: u32 = 0u; i < arrayLength(&spheres); i = i + 1u) {
-        let hit = sphere_intersect(ray, spheres[i]);
-        if hit.hit && (!closest_hit.hit || hit.distance < closest_hit.distance) {
-            closest_hit = hit;
-        }
+    if params.sphere_count + params.triangle_mesh_count == 0u {
+        return closest_hit;
     }
-    for (var i: u32 = 0u; i < arrayLength(&triangle_meshes); i = i + 1u) {
-        let tri_mesh = triangle_meshes[i];
-        if aabb_intersect(ray, tri_mesh.aabb) {
-            for (var j: u32 = 0u; j < tri_mesh.vertex_count / 3u; j = j + 1u) {
-                let hit = triangle_intersect(ray, tri_mesh.start_index + j * 3u, tri_mesh.material.flag == 0u);
-                if hit.hit && (!closest_hit.hit || hit.distance < closest_hit.distance) {
-                    closest_hit = hit;
-                    closest_hit.material = tri_mesh.material;
+    var stack: array<u32, BVH_STACK_SIZE>;
+    var stack_size = 1u;
+    stack[0] = 0u;
+
+    while stack_size > 0u {
+        stack_size = stack_size - 1u;
+        let node = bvh_nodes[stack[stack_size]];
+        if aabb_distance(ray, node.min, node.max) >= closest_distance(closest_hit) {
+            continue;
+        }
+
+        if node.count > 0u {
+            for (var j: u32 = node.left_first; j < node.left_first + node.count; j = j + 1u) {
+                let object = tlas_objects[j];
+                if object < params.sphere_count {
+                    let hit = sphere_intersect(ray, spheres[object]);
+                    if hit.hit && hit.distance < closest_distance(closest_hit) {
+                        closest_hit = hit;
+                        closest_hit.object = object;
+                    }
+                } else {
+                    mesh_intersect(ray, triangle_meshes[object - params.sphere_count], object, &closest_hit);
                 }
             }
+        } else {
+            // visit the nearer child first so the farther one can be culled
+            let left = bvh_nodes[node.left_first];
+            let right = bvh_nodes[node.left_first + 1u];
+            if aabb_distance(ray, left.min, left.max) < aabb_distance(ray, right.min, right.max) {
+                stack[stack_size] = node.left_first + 1u;
+                stack[stack_size + 1u] = node.left_first;
+            } else {
+                stack[stack_size] = node.left_first;
+                stack[stack_size + 1u] = node.left_first + 1u;
+            }
+            stack_size = stack_size + 2u;
         }
     }
     return closest_hit;
 }
 
-fn aabb_intersect(ray: Ray, aabb: Aabb) -> bool {
+fn mesh_intersect(ray: Ray, tri_mesh: TriangleMesh, object: u32, closest_hit: ptr<function, RayHit>) {
+    if tri_mesh.index_count == 0u {
+        return;
+    }
+    var stack: array<u32, BVH_STACK_SIZE>;
+    var stack_size = 1u;
+    stack[0] = tri_mesh.bvh_root;
+
+    while stack_size > 0u {
+        stack_size = stack_size - 1u;
+        let node = bvh_nodes[stack[stack_size]];
+        if aabb_distance(ray, node.min, node.max) >= closest_distance(*closest_hit) {
+            continue;
+        }
+
+        if node.count > 0u {
+            for (var j: u32 = node.left_first; j < node.left_first + node.count; j = j + 1u) {
+                let hit = triangle_intersect(ray, tri_mesh, j, (tri_mesh.material.flags & TWO_SIDED) != 0u);
+                if hit.hit && hit.distance < closest_distance(*closest_hit) {
+                    *closest_hit = hit;
+                    (*closest_hit).object = object;
+                    (*closest_hit).triangle = j;
+                    (*closest_hit).material = tri_mesh.material;
+                }
+            }
+        } else {
+            // visit the nearer child first so the farther one can be culled
+            let left = bvh_nodes[node.left_first];
+            let right = bvh_nodes[node.left_first + 1u];
+            if aabb_distance(ray, left.min, left.max) < aabb_distance(ray, right.min, right.max) {
+                stack[stack_size] = node.left_first + 1u;
+                stack[stack_size + 1u] = node.left_first;
+            } else {
+                stack[stack_size] = node.left_first;
+                stack[stack_size + 1u] = node.left_first + 1u;
+            }
+            stack_size = stack_size + 2u;
+        }
+    }
+}
+
+fn closest_distance(hit: RayHit) -> f32 {
+    return select(NO_HIT, hit.distance, hit.hit);
+}
+
+// distance to the box along the ray, or NO_HIT if it's missed
+fn aabb_distance(ray: Ray, aabb_min: vec3f, aabb_max: vec3f) -> f32 {
     let inv_dir = 1.0 / ray.direction;
-    let t1 = (aabb.min.xyz - ray.origin) * inv_dir;
-    let t2 = (aabb.max.xyz - ray.origin) * inv_dir;
+    let t1 = (aabb_min - ray.origin) * inv_dir;
+    let t2 = (aabb_max - ray.origin) * inv_dir;
 
     let tmin = max(max(min(t1.x, t2.x), min(t1.y, t2.y)), min(t1.z, t2.z));
     let tmax = min(min(max(t1.x, t2.x), max(t1.y, t2.y)), max(t1.z, t2.z));
 
-    return tmax >= max(tmin, 0.0);
+    if tmax >= max(tmin, 0.0) {
+        return max(tmin, 0.0);
+    }
+    return NO_HIT;
 }
 
 fn sphere_intersect(ray: Ray, sphere: Sphere) -> RayHit {
@@ -226,25 +1334,51 @@ fn sphere_intersect(ray: Ray, sphere: Sphere) -> RayHit {
     let c = dot(oc, oc) - sphere.radius * sphere.radius;
     let discriminant = b * b - 4.0 * a * c;
     if discriminant >= 0.0 {
-        let t = (-b - sqrt(discriminant)) / (2.0 * a);
+        // rays starting inside the sphere hit its far side
+        var t = (-b - sqrt(discriminant)) / (2.0 * a);
+        if t <= 1e-5 {
+            t = (-b + sqrt(discriminant)) / (2.0 * a);
+        }
         if t > 1e-5 {
             hit.distance = t;
             hit.material = sphere.material;
             hit.position = ray.origin + t * ray.direction;
-            hit.normal = normalize(hit.position - sphere.position);
-            hit.is_backface = dot(ray.direction, hit.normal) < 0.0;
+            let outward = normalize(hit.position - sphere.position);
+            hit.is_backface = dot(ray.direction, outward) > 0.0;
+            let facing = select(1.0, -1.0, hit.is_backface);
+            hit.normal = outward * facing;
+            hit.uv = sphere_uv(outward);
+            hit.tangent = sphere_tangent(outward) * facing;
             hit.hit = true;
         }
     }
     return hit;
 }
 
+// longitude and latitude of the outward normal, with v = 0 at the top
+fn sphere_uv(n: vec3f) -> vec2f {
+    return vec2f(0.5 - atan2(n.z, n.x) / (2.0 * PI), acos(clamp(n.y, -1.0, 1.0)) / PI);
+}
+
+// points along increasing u, around the y axis. zero at the poles
+fn sphere_tangent(n: vec3f) -> vec4f {
+    let t = vec3f(n.z, 0.0, -n.x);
+    if dot(t, t) < 1e-12 {
+        return vec4f(0.0);
+    }
+    return vec4f(normalize(t), 1.0);
+}
+
 // moller-trumbore algorithm
-fn triangle_intersect(ray: Ray, v0_idx: u32, detect_backface: bool) -> RayHit {
+fn triangle_intersect(ray: Ray, tri_mesh: TriangleMesh, triangle: u32, two_sided: bool) -> RayHit {
     var hit: RayHit;
-    let v0 = triangle_vertices[v0_idx].xyz;
-    let v1 = triangle_vertices[v0_idx + 1u].xyz;
-    let v2 = triangle_vertices[v0_idx + 2u].xyz;
+    let first = tri_mesh.first_index + triangle * 3u;
+    let vert0 = triangle_vertices[tri_mesh.base_vertex + triangle_indices[first]];
+    let vert1 = triangle_vertices[tri_mesh.base_vertex + triangle_indices[first + 1u]];
+    let vert2 = triangle_vertices[tri_mesh.base_vertex + triangle_indices[first + 2u]];
+    let v0 = vert0.position;
+    let v1 = vert1.position;
+    let v2 = vert2.position;
 
     let edge1 = v1 - v0;
     let edge2 = v2 - v0;
@@ -276,15 +1410,27 @@ fn triangle_intersect(ray: Ray, v0_idx: u32, detect_backface: bool) -> RayHit {
 
     let tri_face_vector = cross(edge1, edge2);
     let determinant = dot(tri_face_vector, ray.direction);
+    // one-sided triangles are only hit from the side their vertices wind counter-clockwise on
     var is_valid: bool;
-    if detect_backface {
+    if two_sided {
         is_valid = abs(determinant) >= 1e-8;
     } else {
-        is_valid = determinant >= 1e-8;
+        is_valid = determinant <= -1e-8;
     }
 
     hit.hit = is_valid && t > 1e-5 && u >= 0.0 && v >= 0.0 && w >= 0.0;
     hit.normal = normalize(tri_face_vector) * -sign(determinant);
+    // interpolated normals are kept on the same side as the face normal we hit, and the
+    // tangent frame is flipped with them
+    let smooth_normal = vert0.normal * w + vert1.normal * u + vert2.normal * v;
+    let has_smooth_normal = dot(smooth_normal, smooth_normal) > 1e-12;
+    let outward = select(normalize(tri_face_vector), normalize(smooth_normal), has_smooth_normal);
+    let facing = select(-1.0, 1.0, dot(outward, hit.normal) >= 0.0);
+    if tri_mesh.flat_shading == 0u && has_smooth_normal {
+        hit.normal = outward * facing;
+    }
+    hit.tangent = (vert0.tangent * w + vert1.tangent * u + vert2.tangent * v) * facing;
+    hit.uv = vec2f(vert0.u, vert0.v) * w + vec2f(vert1.u, vert1.v) * u + vec2f(vert2.u, vert2.v) * v;
     hit.distance = t;
     hit.position = ray.origin + t * ray.direction;
     hit.is_backface = determinant > 0.0;
@@ -293,18 +1439,22 @@ fn triangle_intersect(ray: Ray, v0_idx: u32, detect_backface: bool) -> RayHit {
 
 @compute @workgroup_size(4, 4)
 fn main(@builtin(global_invocation_id) global_ix: vec3<u32>) {
+    // the last workgroups hang over the edge when the size isn't a multiple of 4
+    if global_ix.x >= params.width || global_ix.y >= params.height {
+        return;
+    }
     let frag_coord = vec2f(global_ix.xy) / vec2f(f32(params.width), f32(params.height)) ;
 
-    var state = hash(frag_coord.xy + params.random_seed);
+    var state = pixel_sampler(global_ix.xy, frag_coord);
 
     let aspect_ratio = f32(params.width) / f32(params.height);
     let half_fov_tan = tan(radians(60.0) * 0.5);
     let px = (2.0 * frag_coord.x - 1.0) * half_fov_tan * aspect_ratio;
     let py = (1.0 - 2.0 * frag_coord.y) * half_fov_tan;
-    let ray_dir = normalize(params.camera_dir * vec3f(px, py, -1.0) + random_direction(&state) * 0.001);
+    let ray_dir = params.camera_dir * vec3f(px, py, -1.0);
     var ray = Ray(params.camera_pos, ray_dir);
     let last_frame = textureLoad(output_tex, vec2i(global_ix.xy)).rgb;
-    var frag_color = trace(&ray, &state);
+    var frag_color = trace(&ray, &state) * exp2(params.exposure);
 
     if params.accumulated_frames > 5u {
         frag_color = mix(last_frame, frag_color, 1.0 / f32(params.accumulated_frames - 5u));
@@ -0,0 +1,127 @@
+use std::sync::OnceLock;
+
+/// Width and height of the tile, which repeats across the image.
+pub const SIZE: u32 = 64;
+
+/// Spread of the filter that measures how clustered points are, in pixels.
+const SIGMA: f32 = 1.9;
+
+/// A tile of values in 0..1 where every value appears once and similar ones are spread
+/// far apart, so thresholding it at any level gives evenly spaced points. Generated once
+/// by Ulichney's void-and-cluster method and shared from then on.
+pub fn tile() -> &'static [f32] {
+    static TILE: OnceLock<Vec<f32>> = OnceLock::new();
+    TILE.get_or_init(|| {
+        let count = (SIZE * SIZE) as usize;
+        void_and_cluster()
+            .into_iter()
+            .map(|rank| (rank as f32 + 0.5) / count as f32)
+            .collect()
+    })
+}
+
+/// Gaussian weighted sum of the points around each pixel, wrapping around the edges.
+#[derive(Clone)]
+struct Energy {
+    kernel: Vec<f32>,
+    values: Vec<f32>,
+}
+
+impl Energy {
+    fn new() -> Self {
+        let kernel = (0..SIZE * SIZE)
+            .map(|i| {
+                // shortest distance across the wrapped edges
+                let wrap = |d: u32| d.min(SIZE - d) as f32;
+                let (dx, dy) = (wrap(i % SIZE), wrap(i / SIZE));
+                (-(dx * dx + dy * dy) / (2.0 * SIGMA * SIGMA)).exp()
+            })
+            .collect();
+        Self {
+            kernel,
+            values: vec![0.0; (SIZE * SIZE) as usize],
+        }
+    }
+
+    fn update(&mut self, pixel: usize, sign: f32) {
+        let (px, py) = (pixel as u32 % SIZE, pixel as u32 / SIZE);
+        for (i, value) in self.values.iter_mut().enumerate() {
+            let dx = (i as u32 % SIZE + SIZE - px) % SIZE;
+            let dy = (i as u32 / SIZE + SIZE - py) % SIZE;
+            *value += sign * self.kernel[(dy * SIZE + dx) as usize];
+        }
+    }
+
+    /// The point with the most others nearby.
+    fn tightest_cluster(&self, points: &[bool]) -> usize {
+        (0..points.len())
+            .filter(|&i| points[i])
+            .max_by(|&a, &b| self.values[a].total_cmp(&self.values[b]))
+            .unwrap()
+    }
+
+    /// The empty pixel furthest from every point.
+    fn largest_void(&self, points: &[bool]) -> usize {
+        (0..points.len())
+            .filter(|&i| !points[i])
+            .min_by(|&a, &b| self.values[a].total_cmp(&self.values[b]))
+            .unwrap()
+    }
+}
+
+/// The order each pixel is added in, from 0 to `SIZE * SIZE - 1`.
+fn void_and_cluster() -> Vec<u32> {
+    let count = (SIZE * SIZE) as usize;
+    let mut points = vec![false; count];
+    let mut energy = Energy::new();
+
+    // a tenth of the pixels at random, made even by moving the most crowded point into
+    // the biggest gap until that puts it right back, or for long enough that it must be
+    // going around in circles
+    let mut state = 0x9e3779b9u32;
+    let initial = count / 10;
+    let mut placed = 0;
+    while placed < initial {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        let pixel = state as usize % count;
+        if !points[pixel] {
+            points[pixel] = true;
+            energy.update(pixel, 1.0);
+            placed += 1;
+        }
+    }
+    for _ in 0..count {
+        let cluster = energy.tightest_cluster(&points);
+        points[cluster] = false;
+        energy.update(cluster, -1.0);
+        let void = energy.largest_void(&points);
+        points[void] = true;
+        energy.update(void, 1.0);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut ranks = vec![0; count];
+
+    // the initial points are ranked by taking away the most crowded one each time
+    let (mut removed_points, mut removed_energy) = (points.clone(), energy.clone());
+    for rank in (0..initial).rev() {
+        let cluster = removed_energy.tightest_cluster(&removed_points);
+        removed_points[cluster] = false;
+        removed_energy.update(cluster, -1.0);
+        ranks[cluster] = rank as u32;
+    }
+
+    // and the rest by filling the biggest gap each time, which past half full is the
+    // same as Ulichney's third phase of removing the most crowded empty pixel
+    for rank in initial..count {
+        let void = energy.largest_void(&points);
+        points[void] = true;
+        energy.update(void, 1.0);
+        ranks[void] = rank as u32;
+    }
+    ranks
+}
@@ -0,0 +1,313 @@
+use glam::Vec3;
+
+use crate::utils::*;
+
+/// Deepest a tree may get; the shader's traversal stack is sized to match.
+pub const MAX_DEPTH: usize = 32;
+
+const BINS: usize = 12;
+const MAX_LEAF_SIZE: usize = 4;
+const TRAVERSAL_COST: f32 = 1.0;
+const INTERSECTION_COST: f32 = 1.0;
+
+#[derive(Copy, Clone)]
+struct Bounds {
+    min: Vec3,
+    max: Vec3,
+}
+
+impl Bounds {
+    const EMPTY: Self = Self {
+        min: Vec3::INFINITY,
+        max: Vec3::NEG_INFINITY,
+    };
+
+    fn grow(self, other: Bounds) -> Self {
+        Self {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    fn grow_point(self, p: Vec3) -> Self {
+        Self {
+            min: self.min.min(p),
+            max: self.max.max(p),
+        }
+    }
+
+    fn surface_area(&self) -> f32 {
+        let e = (self.max - self.min).max(Vec3::ZERO);
+        2.0 * (e.x * e.y + e.y * e.z + e.z * e.x)
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Primitive {
+    bounds: Bounds,
+    centroid: Vec3,
+}
+
+/// Builds a binned surface area heuristic BVH over the triangles in `indices` (three
+/// per triangle). Returns the flattened nodes with the root at index 0, and the order the
+/// triangles have to be stored in so that every leaf covers a contiguous range.
+pub fn build(vertices: &[Vertex], indices: &[u32]) -> (Vec<BvhNode>, Vec<u32>) {
+    let primitives = indices
+        .chunks_exact(3)
+        .map(|tri| {
+            let bounds = tri.iter().fold(Bounds::EMPTY, |b, &i| {
+                b.grow_point(vertices[i as usize].position)
+            });
+            Primitive {
+                bounds,
+                centroid: (bounds.min + bounds.max) * 0.5,
+            }
+        })
+        .collect::<Vec<_>>();
+    build_primitives(&primitives)
+}
+
+/// Top-level BVH over whole objects, so rays only visit the spheres and meshes whose
+/// bounds they pass through.
+pub struct Tlas {
+    /// Root at index 0, leaves cover `objects[left_first..left_first + count]`.
+    pub nodes: Vec<BvhNode>,
+    /// Sphere indices first, then meshes offset by the sphere count.
+    pub objects: Vec<u32>,
+}
+
+impl Tlas {
+    pub fn new(spheres: &[Sphere], meshes: &[TriangleMesh]) -> Self {
+        let spheres = spheres.iter().map(|sphere| Bounds {
+            min: sphere.position - sphere.radius,
+            max: sphere.position + sphere.radius,
+        });
+        let meshes = meshes.iter().map(|mesh| Bounds {
+            min: mesh.aabb.min.truncate(),
+            max: mesh.aabb.max.truncate(),
+        });
+        let primitives = spheres
+            .chain(meshes)
+            .map(|bounds| Primitive {
+                bounds,
+                centroid: (bounds.min + bounds.max) * 0.5,
+            })
+            .collect::<Vec<_>>();
+        let (nodes, objects) = build_primitives(&primitives);
+        Self { nodes, objects }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+}
+
+fn build_primitives(primitives: &[Primitive]) -> (Vec<BvhNode>, Vec<u32>) {
+    let mut order = (0..primitives.len() as u32).collect::<Vec<_>>();
+    let mut nodes = Vec::with_capacity(primitives.len().max(1) * 2);
+    nodes.push(BvhNode::default());
+    if !primitives.is_empty() {
+        subdivide(
+            primitives,
+            &mut order,
+            &mut nodes,
+            0,
+            0,
+            primitives.len(),
+            0,
+        );
+    }
+    (nodes, order)
+}
+
+fn subdivide(
+    primitives: &[Primitive],
+    order: &mut [u32],
+    nodes: &mut Vec<BvhNode>,
+    node_index: usize,
+    first: usize,
+    count: usize,
+    depth: usize,
+) {
+    let range = &mut order[first..first + count];
+    let bounds = range
+        .iter()
+        .fold(Bounds::EMPTY, |b, &i| b.grow(primitives[i as usize].bounds));
+    nodes[node_index] = BvhNode {
+        min: bounds.min,
+        left_first: first as u32,
+        max: bounds.max,
+        count: count as u32,
+    };
+
+    if count <= 1 || depth + 1 >= MAX_DEPTH {
+        return;
+    }
+
+    let leaf_cost = INTERSECTION_COST * count as f32;
+    let split = find_split(primitives, range);
+    let Some((axis, position, cost)) = split else {
+        return;
+    };
+    let cost = TRAVERSAL_COST + cost / bounds.surface_area().max(f32::EPSILON);
+    if cost >= leaf_cost && count <= MAX_LEAF_SIZE {
+        return;
+    }
+
+    // partition in place around the split plane
+    let mut left_count = 0;
+    for i in 0..count {
+        if primitives[range[i] as usize].centroid[axis] < position {
+            range.swap(i, left_count);
+            left_count += 1;
+        }
+    }
+    if left_count == 0 || left_count == count {
+        return;
+    }
+
+    let left = nodes.len();
+    nodes.push(BvhNode::default());
+    nodes.push(BvhNode::default());
+    nodes[node_index].left_first = left as u32;
+    nodes[node_index].count = 0;
+
+    subdivide(primitives, order, nodes, left, first, left_count, depth + 1);
+    subdivide(
+        primitives,
+        order,
+        nodes,
+        left + 1,
+        first + left_count,
+        count - left_count,
+        depth + 1,
+    );
+}
+
+/// Returns the axis, plane position and unnormalized SAH cost of the cheapest split.
+fn find_split(primitives: &[Primitive], range: &[u32]) -> Option<(usize, f32, f32)> {
+    let centroid_bounds = range.iter().fold(Bounds::EMPTY, |b, &i| {
+        b.grow_point(primitives[i as usize].centroid)
+    });
+
+    let mut best: Option<(usize, f32, f32)> = None;
+    for axis in 0..3 {
+        let lo = centroid_bounds.min[axis];
+        let hi = centroid_bounds.max[axis];
+        if hi <= lo {
+            continue;
+        }
+
+        let scale = BINS as f32 / (hi - lo);
+        let mut bins = [(Bounds::EMPTY, 0usize); BINS];
+        for &i in range {
+            let primitive = &primitives[i as usize];
+            let bin = (((primitive.centroid[axis] - lo) * scale) as usize).min(BINS - 1);
+            bins[bin].0 = bins[bin].0.grow(primitive.bounds);
+            bins[bin].1 += 1;
+        }
+
+        // sweep from both sides so every plane is evaluated in linear time
+        let mut right_area = [0.0; BINS - 1];
+        let mut right_count = [0; BINS - 1];
+        let mut acc = (Bounds::EMPTY, 0);
+        for i in (1..BINS).rev() {
+            acc = (acc.0.grow(bins[i].0), acc.1 + bins[i].1);
+            right_area[i - 1] = acc.0.surface_area();
+            right_count[i - 1] = acc.1;
+        }
+        let mut acc = (Bounds::EMPTY, 0);
+        for i in 0..BINS - 1 {
+            acc = (acc.0.grow(bins[i].0), acc.1 + bins[i].1);
+            if acc.1 == 0 || right_count[i] == 0 {
+                continue;
+            }
+            let cost = INTERSECTION_COST
+                * (acc.0.surface_area() * acc.1 as f32 + right_area[i] * right_count[i] as f32);
+            if best.is_none_or(|(_, _, best_cost)| cost < best_cost) {
+                best = Some((axis, lo + (i + 1) as f32 / scale, cost));
+            }
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec2;
+
+    use super::*;
+
+    /// A row of small triangles spread along X, with a few stacked up in Y.
+    fn triangles(count: u32) -> Vec<Vertex> {
+        (0..count)
+            .flat_map(|i| {
+                let base = Vec3::new(i as f32 * 1.5, (i % 3) as f32, 0.0);
+                [base, base + Vec3::X, base + Vec3::Y].map(|p| Vertex::new(p, Vec3::Z, Vec2::ZERO))
+            })
+            .collect()
+    }
+
+    /// Checks every node below `index` and returns the triangles its leaves cover.
+    fn walk(
+        nodes: &[BvhNode],
+        index: usize,
+        depth: usize,
+        triangle_bounds: &[Bounds],
+        order: &[u32],
+    ) -> Vec<u32> {
+        assert!(depth < MAX_DEPTH);
+        let node = nodes[index];
+        let covered = if node.count > 0 {
+            assert!(node.count as usize <= MAX_LEAF_SIZE);
+            let first = node.left_first as usize;
+            order[first..first + node.count as usize].to_vec()
+        } else {
+            let left = node.left_first as usize;
+            let mut covered = walk(nodes, left, depth + 1, triangle_bounds, order);
+            covered.extend(walk(nodes, left + 1, depth + 1, triangle_bounds, order));
+            covered
+        };
+        for &triangle in &covered {
+            let bounds = triangle_bounds[triangle as usize];
+            assert!(bounds.min.cmpge(node.min).all() && bounds.max.cmple(node.max).all());
+        }
+        covered
+    }
+
+    #[test]
+    fn leaves_cover_every_triangle_once_within_bounds() {
+        let vertices = triangles(100);
+        let indices = (0..300).collect::<Vec<_>>();
+        let (nodes, order) = build(&vertices, &indices);
+        let triangle_bounds = vertices
+            .chunks_exact(3)
+            .map(|tri| {
+                tri.iter()
+                    .fold(Bounds::EMPTY, |b, v| b.grow_point(v.position))
+            })
+            .collect::<Vec<_>>();
+
+        let mut covered = walk(&nodes, 0, 0, &triangle_bounds, &order);
+        covered.sort();
+        assert_eq!(covered, (0..100).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn empty_input_gives_an_empty_root() {
+        let (nodes, order) = build(&[], &[]);
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(nodes[0].count, 0);
+        assert!(order.is_empty());
+    }
+
+    #[test]
+    fn identical_triangles_stay_in_one_leaf() {
+        // no plane separates them, so splitting can't help
+        let vertices = triangles(1);
+        let indices = [0, 1, 2].repeat(8);
+        let (nodes, order) = build(&vertices, &indices);
+        assert_eq!(nodes[0].count, 8);
+        assert_eq!(order.len(), 8);
+    }
+}
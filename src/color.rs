@@ -0,0 +1,77 @@
+//! Colorimetry shared by the renderer and scene loading: the CIE observer, conversions to
+//! linear sRGB, and the scale photometric units are converted with.
+
+use glam::*;
+
+/// CIE XYZ to linear sRGB, balanced so a flat spectrum comes out white and scaled by the
+/// width of the wavelength range to undo its sampling probability.
+pub const XYZ_TO_RGB: Mat3 = Mat3::from_cols_array(&[
+    10.0983, -3.8165, 0.2296, -4.7902, 7.3888, -0.8408, -1.5537, 0.1635, 4.3565,
+]);
+/// CIE XYZ to linear sRGB with the D65 white point, for the Preetham sky's colors.
+pub const XYZ_TO_SRGB: Mat3 = Mat3::from_cols_array(&[
+    3.2406, -0.9689, 0.0557, -1.5372, 1.8758, -0.2040, -0.4986, 0.0415, 1.0570,
+]);
+/// Rec. 709 weights giving the luminance of a linear sRGB color.
+pub const LUMINANCE: Vec3 = Vec3::new(0.2126, 0.7152, 0.0722);
+/// Luminance in cd/m² of a unit of radiance, so that the sun overhead lights a white
+/// surface to about 1. Photometric quantities in scenes are converted with it too.
+pub const DAYLIGHT_UNIT: f32 = 1e5 / std::f32::consts::PI;
+
+/// CIE 1931 color matching functions, fitted by Wyman et al. 2013.
+pub fn cie_xyz(wavelength: f32) -> Vec3 {
+    let lobe = |mu: f32, below: f32, above: f32| {
+        let t = (wavelength - mu) / if wavelength < mu { below } else { above };
+        (-0.5 * t * t).exp()
+    };
+    Vec3::new(
+        1.056 * lobe(599.8, 37.9, 31.0) + 0.362 * lobe(442.0, 16.0, 26.7)
+            - 0.065 * lobe(501.1, 20.4, 26.2),
+        0.821 * lobe(568.8, 46.9, 40.5) + 0.286 * lobe(530.9, 16.3, 31.1),
+        1.217 * lobe(437.0, 11.8, 36.0) + 0.681 * lobe(459.0, 26.0, 13.8),
+    )
+}
+
+/// Linear sRGB that a unit of radiance at `wavelength` contributes to a pixel.
+pub fn wavelength_to_rgb(wavelength: f32) -> Vec3 {
+    XYZ_TO_RGB * cie_xyz(wavelength)
+}
+
+/// Linear sRGB of a blackbody's glow at `kelvin`, scaled to a luminance of 1, with
+/// whatever falls outside sRGB clipped.
+pub fn blackbody(kelvin: f32) -> Vec3 {
+    // Planck's law with wavelengths in µm, whose constant factors cancel in the scaling;
+    // much colder and the visible part underflows
+    let kelvin = kelvin.max(500.0);
+    let xyz = (0..=80)
+        .map(|i| {
+            let wavelength = 380.0 + i as f32 * 5.0;
+            let microns = wavelength * 1e-3;
+            let radiance = 1.0 / (microns.powi(5) * ((14388.0 / (microns * kelvin)).exp() - 1.0));
+            cie_xyz(wavelength) * radiance
+        })
+        .sum::<Vec3>();
+    (XYZ_TO_SRGB * (xyz / xyz.y)).max(Vec3::ZERO)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn d65_blackbody_is_about_white() {
+        // the D65 white point is close to, but not on, the Planckian locus
+        let rgb = blackbody(6504.0);
+        let luminance = rgb.dot(LUMINANCE);
+        assert!((luminance - 1.0).abs() < 0.02, "{rgb}");
+        assert!(rgb.max_element() - rgb.min_element() < 0.1, "{rgb}");
+    }
+
+    #[test]
+    fn blackbodies_redden_as_they_cool() {
+        let (candle, sky) = (blackbody(1900.0), blackbody(12000.0));
+        assert!(candle.x > candle.z);
+        assert!(sky.z > sky.x);
+        assert_eq!(blackbody(0.0), blackbody(500.0));
+    }
+}
@@ -0,0 +1,629 @@
+use glam::Vec3;
+use image::RgbaImage;
+use wgpu::util::DeviceExt;
+
+use crate::{
+    Scene, blue_noise,
+    bvh::Tlas,
+    environment::Environment,
+    ies::{self, IesProfile},
+    lights, texture,
+    utils::*,
+};
+
+pub const OUTPUT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+
+pub struct OutputTexture {
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+}
+
+impl OutputTexture {
+    pub fn new(device: &wgpu::Device, width: u32, height: u32) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: None,
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: OUTPUT_FORMAT,
+            usage: wgpu::TextureUsages::STORAGE_BINDING
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&Default::default());
+        Self { texture, view }
+    }
+}
+
+pub struct SceneBuffers {
+    pub spheres: wgpu::Buffer,
+    pub triangle_vertices: wgpu::Buffer,
+    pub triangle_indices: wgpu::Buffer,
+    pub triangle_meshes: wgpu::Buffer,
+    pub bvh_nodes: wgpu::Buffer,
+    pub tlas_objects: wgpu::Buffer,
+    pub lights: wgpu::Buffer,
+    pub analytic_lights: wgpu::Buffer,
+    pub profiles: wgpu::TextureView,
+    pub textures: wgpu::TextureView,
+    pub environment: wgpu::TextureView,
+    pub environment_cdf: wgpu::TextureView,
+    pub has_environment: bool,
+    pub sphere_count: u32,
+    pub triangle_mesh_count: u32,
+    pub light_count: u32,
+    pub light_power: f32,
+}
+
+impl SceneBuffers {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, scene: &Scene) -> Self {
+        let Scene {
+            spheres,
+            triangles,
+            lights: analytic_lights,
+            profiles,
+            textures,
+            environment,
+            ..
+        } = scene;
+        let triangle_vertices = triangles
+            .iter()
+            .flat_map(|mesh| mesh.vertices.iter().copied())
+            .collect::<Vec<_>>();
+        let triangle_indices = triangles
+            .iter()
+            .flat_map(|mesh| mesh.indices.iter().copied())
+            .collect::<Vec<_>>();
+
+        // the top-level tree comes first so its root is node 0, followed by every mesh's
+        // tree. interior nodes point at their children, so they move with the mesh's nodes
+        let tlas = Tlas::new(spheres, triangles);
+        let tlas_len = tlas.nodes.len() as u32;
+        let mesh_nodes = triangles
+            .iter()
+            .scan(tlas_len, |bvh_root, mesh| {
+                let root = *bvh_root;
+                *bvh_root += mesh.bvh.len() as u32;
+                Some(mesh.bvh.iter().map(move |node| BvhNode {
+                    left_first: if node.count == 0 {
+                        node.left_first + root
+                    } else {
+                        node.left_first
+                    },
+                    ..*node
+                }))
+            })
+            .flatten();
+        let bvh_nodes = tlas.nodes.into_iter().chain(mesh_nodes).collect::<Vec<_>>();
+
+        let gpu_triangles = triangles
+            .iter()
+            .scan(
+                (0, 0, tlas_len),
+                |(base_vertex, first_index, bvh_root), mesh| {
+                    let gpu_mesh = GPUTriangleMesh {
+                        bvh_root: *bvh_root,
+                        base_vertex: *base_vertex,
+                        first_index: *first_index,
+                        index_count: mesh.indices.len() as u32,
+                        flat_shading: mesh.flat_shading as u32,
+                        material: mesh.material,
+                        ..Default::default()
+                    };
+                    *base_vertex += mesh.vertices.len() as u32;
+                    *first_index += gpu_mesh.index_count;
+                    *bvh_root += mesh.bvh.len() as u32;
+                    Some(gpu_mesh)
+                },
+            )
+            .collect::<Vec<GPUTriangleMesh>>();
+
+        let (lights, light_power) = lights::build(spheres, triangles, analytic_lights, profiles);
+        let sphere_count = spheres.len() as u32;
+        let triangle_mesh_count = gpu_triangles.len() as u32;
+        let light_count = lights.len() as u32;
+
+        let spheres = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Sphere Buffer"),
+            contents: bytemuck::cast_slice(&non_empty(spheres.to_vec())),
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+        });
+
+        let triangle_vertices = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Triangle Buffer"),
+            contents: bytemuck::cast_slice(&non_empty(triangle_vertices)),
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+        });
+
+        let triangle_indices = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Triangle Index Buffer"),
+            contents: bytemuck::cast_slice(&non_empty(triangle_indices)),
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+        });
+
+        let triangle_meshes = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("GPU Triangle Mesh Buffer"),
+            contents: bytemuck::cast_slice(&non_empty(gpu_triangles)),
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+        });
+
+        let bvh_nodes = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("BVH Node Buffer"),
+            contents: bytemuck::cast_slice(&non_empty(bvh_nodes)),
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+        });
+
+        let tlas_objects = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("TLAS Object Buffer"),
+            contents: bytemuck::cast_slice(&non_empty(tlas.objects)),
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+        });
+
+        let lights = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Light Buffer"),
+            contents: bytemuck::cast_slice(&non_empty(lights)),
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+        });
+
+        let analytic_lights = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Analytic Light Buffer"),
+            contents: bytemuck::cast_slice(&non_empty(analytic_lights.to_vec())),
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+        });
+
+        let profiles = profile_texture(device, queue, profiles);
+        let textures = texture_array(device, queue, textures);
+        let has_environment = environment.is_some();
+        let (environment, environment_cdf) =
+            environment_textures(device, queue, environment.as_ref());
+
+        Self {
+            spheres,
+            triangle_vertices,
+            triangle_indices,
+            triangle_meshes,
+            bvh_nodes,
+            tlas_objects,
+            lights,
+            analytic_lights,
+            profiles,
+            textures,
+            environment,
+            environment_cdf,
+            has_environment,
+            sphere_count,
+            triangle_mesh_count,
+            light_count,
+            light_power,
+        }
+    }
+}
+
+/// Scales every image to a common size and stacks them into one texture array, since
+/// bindings can't be indexed dynamically. Without images a single white layer is bound.
+fn texture_array(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    images: &[RgbaImage],
+) -> wgpu::TextureView {
+    let (width, height) = texture::layer_size(images);
+    let mut data = Vec::new();
+    for image in images {
+        if image.dimensions() == (width, height) {
+            data.extend_from_slice(image);
+        } else {
+            let resized = image::imageops::resize(
+                image,
+                width,
+                height,
+                image::imageops::FilterType::Triangle,
+            );
+            data.extend_from_slice(&resized);
+        }
+    }
+    if data.is_empty() {
+        data = vec![255; 4];
+    }
+
+    let texture = device.create_texture_with_data(
+        queue,
+        &wgpu::TextureDescriptor {
+            label: Some("Texture Array"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: images.len().max(1) as u32,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        },
+        wgpu::util::TextureDataOrder::LayerMajor,
+        &data,
+    );
+    texture.create_view(&wgpu::TextureViewDescriptor {
+        dimension: Some(wgpu::TextureViewDimension::D2Array),
+        ..Default::default()
+    })
+}
+
+/// The environment map's radiance, and its tables for picking texels: each row's
+/// conditional cdf, then the marginal cdf as an extra last row. Without a map, black
+/// placeholders are bound.
+fn environment_textures(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    environment: Option<&Environment>,
+) -> (wgpu::TextureView, wgpu::TextureView) {
+    let placeholder = Environment::new(1, 1, vec![Vec3::ZERO]);
+    let environment = environment.unwrap_or(&placeholder);
+    let (width, height) = (environment.width, environment.height);
+    let radiance = environment
+        .pixels
+        .iter()
+        .map(|p| p.extend(1.0))
+        .collect::<Vec<_>>();
+
+    // rows are as wide as the widest of the tables
+    let cdf_width = width.max(height);
+    let mut cdf = vec![0.0f32; (cdf_width * (height + 1)) as usize];
+    for (y, row) in environment
+        .conditional_cdf
+        .chunks(width as usize)
+        .chain([environment.marginal_cdf.as_slice()])
+        .enumerate()
+    {
+        let start = y * cdf_width as usize;
+        cdf[start..start + row.len()].copy_from_slice(row);
+    }
+
+    let texture = |label, format, size: (u32, u32), data: &[u8]| {
+        device
+            .create_texture_with_data(
+                queue,
+                &wgpu::TextureDescriptor {
+                    label: Some(label),
+                    size: wgpu::Extent3d {
+                        width: size.0,
+                        height: size.1,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format,
+                    usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                    view_formats: &[],
+                },
+                wgpu::util::TextureDataOrder::LayerMajor,
+                data,
+            )
+            .create_view(&Default::default())
+    };
+    (
+        texture(
+            "Environment Texture",
+            wgpu::TextureFormat::Rgba32Float,
+            (width, height),
+            bytemuck::cast_slice(&radiance),
+        ),
+        texture(
+            "Environment CDF Texture",
+            wgpu::TextureFormat::R32Float,
+            (cdf_width, height + 1),
+            bytemuck::cast_slice(&cdf),
+        ),
+    )
+}
+
+/// Every IES profile's rows one after another, with a blank profile when there are none.
+fn profile_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    profiles: &[IesProfile],
+) -> wgpu::TextureView {
+    let profile_len = (ies::VERTICAL_SAMPLES * ies::HORIZONTAL_SAMPLES) as usize;
+    let mut data = profiles
+        .iter()
+        .flat_map(|profile| profile.intensity.iter().copied())
+        .collect::<Vec<f32>>();
+    if data.is_empty() {
+        data.resize(profile_len, 0.0);
+    }
+    device
+        .create_texture_with_data(
+            queue,
+            &wgpu::TextureDescriptor {
+                label: Some("IES Profile Texture"),
+                size: wgpu::Extent3d {
+                    width: ies::VERTICAL_SAMPLES,
+                    height: (data.len() / ies::VERTICAL_SAMPLES as usize) as u32,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::R32Float,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            },
+            wgpu::util::TextureDataOrder::LayerMajor,
+            bytemuck::cast_slice(&data),
+        )
+        .create_view(&Default::default())
+}
+
+/// Storage buffers can't be bound with a size of zero, so empty arrays get a single
+/// zeroed element. The shader only iterates up to the counts in `IParams`.
+fn non_empty<T: bytemuck::Zeroable>(mut items: Vec<T>) -> Vec<T> {
+    if items.is_empty() {
+        items.push(T::zeroed());
+    }
+    items
+}
+
+pub struct ComputePipeline {
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub pipeline: wgpu::ComputePipeline,
+    pub config: wgpu::Buffer,
+    pub sampler: wgpu::Sampler,
+    pub blue_noise: wgpu::TextureView,
+}
+
+impl ComputePipeline {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let cs_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: None,
+            source: wgpu::ShaderSource::Wgsl(include_str!("../assets/compute.wgsl").into()),
+        });
+        let storage_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let unfilterable_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::ReadWrite,
+                        format: OUTPUT_FORMAT,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                storage_entry(2),
+                storage_entry(3),
+                storage_entry(4),
+                storage_entry(5),
+                storage_entry(6),
+                storage_entry(7),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 8,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 9,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                storage_entry(10),
+                unfilterable_entry(11),
+                unfilterable_entry(12),
+                storage_entry(13),
+                unfilterable_entry(14),
+                unfilterable_entry(15),
+            ],
+        });
+        let compute_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: None,
+                bind_group_layouts: &[&bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: None,
+            layout: Some(&compute_pipeline_layout),
+            module: &cs_module,
+            entry_point: Some("main"),
+            cache: None,
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        });
+        let config = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: size_of::<IParams>() as u64,
+            usage: wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::UNIFORM,
+            mapped_at_creation: false,
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let blue_noise = device
+            .create_texture_with_data(
+                queue,
+                &wgpu::TextureDescriptor {
+                    label: Some("Blue Noise Texture"),
+                    size: wgpu::Extent3d {
+                        width: blue_noise::SIZE,
+                        height: blue_noise::SIZE,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: wgpu::TextureFormat::R32Float,
+                    usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                    view_formats: &[],
+                },
+                wgpu::util::TextureDataOrder::LayerMajor,
+                bytemuck::cast_slice(blue_noise::tile()),
+            )
+            .create_view(&Default::default());
+
+        Self {
+            bind_group_layout,
+            pipeline,
+            config,
+            sampler,
+            blue_noise,
+        }
+    }
+
+    pub fn bind_group(
+        &self,
+        device: &wgpu::Device,
+        output: &OutputTexture,
+        scene: &SceneBuffers,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.config.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&output.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: scene.spheres.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: scene.triangle_vertices.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: scene.triangle_meshes.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: scene.bvh_nodes.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: scene.tlas_objects.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: scene.triangle_indices.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 8,
+                    resource: wgpu::BindingResource::TextureView(&scene.textures),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 9,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 10,
+                    resource: scene.lights.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 11,
+                    resource: wgpu::BindingResource::TextureView(&scene.environment),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 12,
+                    resource: wgpu::BindingResource::TextureView(&scene.environment_cdf),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 13,
+                    resource: scene.analytic_lights.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 14,
+                    resource: wgpu::BindingResource::TextureView(&scene.profiles),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 15,
+                    resource: wgpu::BindingResource::TextureView(&self.blue_noise),
+                },
+            ],
+        })
+    }
+
+    pub fn dispatch(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        bind_group: &wgpu::BindGroup,
+        width: u32,
+        height: u32,
+    ) {
+        let mut cpass = encoder.begin_compute_pass(&Default::default());
+        cpass.set_pipeline(&self.pipeline);
+        cpass.set_bind_group(0, bind_group, &[]);
+        cpass.dispatch_workgroups(width.div_ceil(4), height.div_ceil(4), 1);
+    }
+}
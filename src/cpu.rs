@@ -0,0 +1,2028 @@
+//! CPU port of the integrator in `assets/compute.wgsl`, kept line-for-line close to the
+//! shader so it can serve as a reference when the GPU path changes.
+
+use glam::*;
+
+use crate::{
+    Scene, blue_noise,
+    bvh::Tlas,
+    color::*,
+    environment::Environment,
+    ies::{self, IesProfile},
+    lights, texture,
+    utils::*,
+};
+
+const NO_HIT: f32 = 1e30;
+/// Range of wavelengths in nm that spectral paths are traced at.
+pub const WAVELENGTH_MIN: f32 = 380.0;
+pub const WAVELENGTH_MAX: f32 = 780.0;
+/// Wavelength in nm that dispersive materials are evaluated at outside spectral mode.
+const D_LINE: f32 = 587.6;
+/// Illuminance in lux of sunlight above the atmosphere.
+const SOLAR_ILLUMINANCE: f32 = 128e3;
+
+/// How far bounced rays start off the surface, so they don't hit it again.
+const RAY_OFFSET: f32 = 1e-4;
+
+pub const MAX_BOUNCES: u32 = 5;
+pub const RAYS_PER_PIXEL: u32 = 10;
+
+#[derive(Default, Debug, Copy, Clone)]
+pub struct Ray {
+    pub origin: Vec3,
+    pub direction: Vec3,
+}
+
+#[derive(Default, Debug, Copy, Clone)]
+pub struct RayHit {
+    pub distance: f32,
+    pub position: Vec3,
+    /// Faces back along the ray, whichever side was hit.
+    pub normal: Vec3,
+    pub uv: Vec2,
+    pub tangent: Vec4,
+    /// The ray hit the surface from behind, i.e. from inside a closed object.
+    pub is_backface: bool,
+    /// What was hit, numbered like [`Light::object`] and [`Light::triangle`].
+    pub object: u32,
+    pub triangle: u32,
+    pub material: Material,
+    pub hit: bool,
+}
+
+pub fn hash(seed: Vec2) -> u32 {
+    hash_u32(((seed.x * 73856093.0) as u32) ^ ((seed.y * 19349663.0) as u32))
+}
+
+pub fn hash_u32(seed: u32) -> u32 {
+    let mut h = seed;
+    h = (h ^ (h >> 16)).wrapping_mul(0x45d9f3b);
+    h = (h ^ (h >> 16)).wrapping_mul(0x45d9f3b);
+    h ^ (h >> 16)
+}
+
+/// Where a path gets its random numbers, in the way [`IParams::sampler_kind`] picks. Every
+/// call for one or two numbers moves on to the next dimension of the current sample.
+#[derive(Debug, Copy, Clone)]
+pub struct Sampler {
+    pub kind: u32,
+    /// Running state of the random generator, or a hash of the pixel for the others.
+    pub seed: u32,
+    pub pixel: UVec2,
+    /// Which sample of the pixel this is, counting every ray of every frame.
+    pub index: u32,
+    pub dimension: u32,
+}
+
+/// The sampler for the pixel's first ray this frame, see [`next_sample`].
+pub fn pixel_sampler(pixel: UVec2, frag_coord: Vec2, params: &IParams) -> Sampler {
+    let seed = if params.sampler_kind == SamplerKind::Random as u32 {
+        hash(frag_coord + params.random_seed)
+    } else {
+        hash_u32(pixel.x ^ hash_u32(pixel.y))
+    };
+    Sampler {
+        kind: params.sampler_kind,
+        seed,
+        pixel,
+        index: params.accumulated_frames * RAYS_PER_PIXEL,
+        dimension: 0,
+    }
+}
+
+/// Moves on to the pixel's next ray. Every ray starts at the first dimension, the camera's
+/// jitter, with its bounces' dimensions after it.
+pub fn next_sample(state: &mut Sampler) {
+    state.index += 1;
+    state.dimension = 0;
+}
+
+pub fn next_random(state: &mut u32) -> u32 {
+    *state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+    let result = ((*state >> ((*state >> 28) + 4)) ^ *state).wrapping_mul(277803737);
+    (result >> 22) ^ result
+}
+
+/// Owen scrambling of the bits of `x`, as a hash of their reversal by Laine and Karras,
+/// from Burley 2020, "Practical Hash-based Owen Scrambling".
+pub fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    let mut x = x.reverse_bits().wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x.reverse_bits()
+}
+
+/// The first two dimensions of the Sobol sequence, as fractions of 2^32.
+pub fn sobol(index: u32) -> UVec2 {
+    let mut y = 0;
+    let mut direction = 1u32 << 31;
+    let mut bits = index;
+    while bits != 0 {
+        if bits & 1 != 0 {
+            y ^= direction;
+        }
+        bits >>= 1;
+        direction ^= direction >> 1;
+    }
+    UVec2::new(index.reverse_bits(), y)
+}
+
+/// Two dimensions of a Sobol point, as fractions of 2^32. Each dimension shuffles the
+/// samples differently so they don't line up with the others, while every one of them
+/// stays well spread on its own.
+fn sobol_pair(state: &Sampler) -> UVec2 {
+    let seed = hash_u32(state.seed ^ hash_u32(state.dimension));
+    let point = sobol(nested_uniform_scramble(state.index, seed));
+    UVec2::new(
+        nested_uniform_scramble(point.x, hash_u32(seed ^ 1)),
+        nested_uniform_scramble(point.y, hash_u32(seed ^ 2)),
+    )
+}
+
+/// A value from the blue-noise tile, as a fraction of 2^32, moved around by the dimension
+/// and `salt` so different numbers don't come from the same spot.
+fn blue_noise(state: &Sampler, salt: u32) -> u32 {
+    let tile = blue_noise::tile();
+    let offset = hash_u32(state.dimension ^ hash_u32(salt));
+    let x = (state.pixel.x + (offset & 0xffff)) % blue_noise::SIZE;
+    let y = (state.pixel.y + (offset >> 16)) % blue_noise::SIZE;
+    ((tile[(y * blue_noise::SIZE + x) as usize] * 16777216.0) as u32) << 8
+}
+
+/// Two dimensions of a blue-noise sample, as fractions of 2^32: the R2 sequence, which
+/// spreads successive samples evenly, shifted by the tile.
+fn blue_noise_pair(state: &Sampler) -> UVec2 {
+    UVec2::new(
+        blue_noise(state, 0).wrapping_add(state.index.wrapping_mul(3242174889)),
+        blue_noise(state, 1).wrapping_add(state.index.wrapping_mul(2447445413)),
+    )
+}
+
+/// Fraction of 2^32 to a float below 1.
+fn unit_float(x: u32) -> f32 {
+    (x >> 8) as f32 / 16777216.0
+}
+
+pub fn random_value(state: &mut Sampler) -> f32 {
+    let value = match SamplerKind::from_u32(state.kind) {
+        Some(SamplerKind::Sobol) => unit_float(sobol_pair(state).x),
+        // the golden ratio spreads successive samples evenly in one dimension
+        Some(SamplerKind::BlueNoise) => {
+            unit_float(blue_noise(state, 0).wrapping_add(state.index.wrapping_mul(2654435769)))
+        }
+        _ => next_random(&mut state.seed) as f32 / 4294967295.0,
+    };
+    state.dimension += 1;
+    value
+}
+
+/// Two numbers that, unlike two calls to [`random_value`], are spread evenly together.
+pub fn random_pair(state: &mut Sampler) -> Vec2 {
+    let pair = match SamplerKind::from_u32(state.kind) {
+        Some(SamplerKind::Sobol) => {
+            let pair = sobol_pair(state);
+            Vec2::new(unit_float(pair.x), unit_float(pair.y))
+        }
+        Some(SamplerKind::BlueNoise) => {
+            let pair = blue_noise_pair(state);
+            Vec2::new(unit_float(pair.x), unit_float(pair.y))
+        }
+        _ => Vec2::new(
+            next_random(&mut state.seed) as f32 / 4294967295.0,
+            next_random(&mut state.seed) as f32 / 4294967295.0,
+        ),
+    };
+    state.dimension += 1;
+    pair
+}
+
+/// Uniformly distributed on the unit sphere.
+pub fn random_direction(state: &mut Sampler) -> Vec3 {
+    let u = random_pair(state);
+    let z = 1.0 - 2.0 * u.x;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * std::f32::consts::PI * u.y;
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+pub fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - t * 2.0)
+}
+
+/// `sky_weight` scales the part that paths could also have sampled directly, see
+/// [`sky_light`].
+pub fn get_environment_light(ray: Ray, params: &IParams, scene: &Scene, sky_weight: f32) -> Vec3 {
+    let sky = &params.sky;
+    let sky_gradient = sky.horizon_color.lerp(
+        sky.zenith_color,
+        smoothstep(0.0, 0.4, ray.direction.y).powf(0.35),
+    );
+
+    let ground_to_sky = smoothstep(-0.01, 0.0, ray.direction.y);
+    let background = if params.environment != 0 {
+        Vec3::ZERO
+    } else if sky.turbidity > 0.0 {
+        // the ground reflects the horizon above it
+        let daylight = preetham_sky(ray.direction, params.light_dir, sky);
+        (sky.ground_color * daylight).lerp(daylight, ground_to_sky)
+    } else {
+        sky.ground_color.lerp(sky_gradient, ground_to_sky)
+    };
+
+    background + sky_light(ray.direction, params, scene) * sky_weight
+}
+
+/// The part of the environment that light sampling aims at: the whole environment map if
+/// there is one, otherwise the sun.
+pub fn sky_light(direction: Vec3, params: &IParams, scene: &Scene) -> Vec3 {
+    match &scene.environment {
+        Some(map) if params.environment != 0 => environment_light(direction, map, &params.sky),
+        _ => sun_light(direction, params.light_dir, &params.sky),
+    }
+}
+
+pub fn sun_light(direction: Vec3, light_dir: Vec3, sky: &Sky) -> Vec3 {
+    let sun = if sky.turbidity > 0.0 {
+        let one_minus_cos = sun_cone(sky);
+        let inside = 1.0 - direction.dot(light_dir) <= one_minus_cos;
+        // spreading the same light over a bigger disk only softens shadows
+        let radiance =
+            SOLAR_ILLUMINANCE / DAYLIGHT_UNIT / (2.0 * std::f32::consts::PI * one_minus_cos);
+        sun_transmittance(light_dir.y, sky.turbidity)
+            * radiance
+            * sky.daylight_intensity
+            * inside as u32 as f32
+    } else {
+        direction.dot(light_dir).max(0.0).powf(sky.sun_focus) * sky.sun_intensity * sky.sun_color
+    };
+    // hidden below the horizon
+    let sun_mask = smoothstep(-0.01, 0.0, direction.y) >= 1.0;
+    sun * sun_mask as u32 as f32
+}
+
+/// One minus the cosine of the Preetham sun's angular radius, without cancelling to zero.
+pub fn sun_cone(sky: &Sky) -> f32 {
+    let s = (sky.sun_radius * 0.5).sin();
+    2.0 * s * s
+}
+
+/// Share of sunlight reaching the ground through Rayleigh and aerosol scattering, from
+/// Preetham et al., "A Practical Analytic Model for Daylight" (1999), at the wavelengths
+/// of red, green and blue.
+pub fn sun_transmittance(cos_zenith: f32, turbidity: f32) -> Vec3 {
+    let cos_zenith = cos_zenith.clamp(0.0, 1.0);
+    let zenith = cos_zenith.acos().to_degrees();
+    // relative optical mass of the air the light passes through
+    let air_mass = 1.0 / (cos_zenith + 0.15 * (93.885 - zenith).powf(-1.253));
+    let wavelength = Vec3::new(0.68, 0.55, 0.44);
+    let rayleigh = (-0.008735 * wavelength.powf(-4.08) * air_mass).exp();
+    let beta = 0.04608 * turbidity - 0.04586;
+    let aerosol = (-beta * wavelength.powf(-1.3) * air_mass).exp();
+    rayleigh * aerosol
+}
+
+/// Radiance of the clear sky towards `direction`, from the Preetham model's fit of
+/// luminance and chromaticity, or towards the horizon when `direction` is below it.
+pub fn preetham_sky(direction: Vec3, light_dir: Vec3, sky: &Sky) -> Vec3 {
+    let t = sky.turbidity;
+    // Perez coefficients for luminance Y and chromaticities x and y
+    let a = Vec3::new(0.1787, -0.0193, -0.0167) * t + Vec3::new(-1.4630, -0.2592, -0.2608);
+    let b = Vec3::new(-0.3554, -0.0665, -0.0950) * t + Vec3::new(0.4275, 0.0008, 0.0092);
+    let c = Vec3::new(-0.0227, -0.0004, -0.0079) * t + Vec3::new(5.3251, 0.2125, 0.2102);
+    let d = Vec3::new(0.1206, -0.0641, -0.0441) * t + Vec3::new(-2.5771, -0.8989, -1.6537);
+    let e = Vec3::new(-0.0670, -0.0033, -0.0109) * t + Vec3::new(0.3703, 0.0452, 0.0529);
+    let perez = |cos_theta: f32, gamma: f32| {
+        (Vec3::ONE + a * (b / cos_theta.max(1e-3)).exp())
+            * (Vec3::ONE + c * (d * gamma).exp() + e * gamma.cos() * gamma.cos())
+    };
+
+    // the model ends at sunset, so keep the sun there and fade out through twilight
+    let sun_theta = light_dir.y.clamp(0.0, 1.0).acos();
+    let chi = (4.0 / 9.0 - t / 120.0) * (std::f32::consts::PI - 2.0 * sun_theta);
+    let zenith_luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+    let theta = Vec3::new(sun_theta.powi(3), sun_theta.powi(2), sun_theta);
+    let zenith_x = t * t * theta.dot(Vec3::new(0.00166, -0.00375, 0.00209))
+        + t * (theta.dot(Vec3::new(-0.02903, 0.06377, -0.03202)) + 0.00394)
+        + theta.dot(Vec3::new(0.11693, -0.21196, 0.06052))
+        + 0.25886;
+    let zenith_y = t * t * theta.dot(Vec3::new(0.00275, -0.00610, 0.00317))
+        + t * (theta.dot(Vec3::new(-0.04214, 0.08970, -0.04153)) + 0.00516)
+        + theta.dot(Vec3::new(0.15346, -0.26756, 0.06670))
+        + 0.26688;
+    let zenith = Vec3::new(zenith_luminance, zenith_x, zenith_y);
+
+    let gamma = direction.dot(light_dir).clamp(-1.0, 1.0).acos();
+    let luminance_xy = zenith * perez(direction.y, gamma) / perez(1.0, sun_theta);
+    // kcd/m², fading through twilight
+    let luminance = luminance_xy.x.max(0.0) * 1e3 / DAYLIGHT_UNIT
+        * smoothstep(-0.1, 0.0, light_dir.y)
+        * sky.daylight_intensity;
+    let (x, y) = (luminance_xy.y, luminance_xy.z);
+    let xyz = Vec3::new(x / y, 1.0, (1.0 - x - y) / y) * luminance;
+    (XYZ_TO_SRGB * xyz).max(Vec3::ZERO)
+}
+
+/// Share of light samples spent on the sky rather than on emissive objects.
+pub fn sky_chance(params: &IParams) -> f32 {
+    let lit = if params.environment != 0 {
+        params.sky.environment_intensity > 0.0
+    } else if params.sky.turbidity > 0.0 {
+        params.sky.daylight_intensity > 0.0 && params.light_dir != Vec3::ZERO
+    } else {
+        params.sky.sun_intensity > 0.0 && params.light_dir != Vec3::ZERO
+    };
+    if !lit {
+        0.0
+    } else if params.light_count == 0 {
+        1.0
+    } else {
+        0.5
+    }
+}
+
+/// Solid angle density of light sampling picking `direction` towards the sky. The sun's
+/// lobe or disk is sampled exactly, environment maps texel by texel.
+pub fn sky_pdf(direction: Vec3, params: &IParams, scene: &Scene) -> f32 {
+    let pdf = match &scene.environment {
+        Some(map) if params.environment != 0 => environment_pdf(direction, map, &params.sky),
+        _ if params.sky.turbidity > 0.0 => {
+            let one_minus_cos = sun_cone(&params.sky);
+            let inside = 1.0 - direction.dot(params.light_dir) <= one_minus_cos;
+            inside as u32 as f32 / (2.0 * std::f32::consts::PI * one_minus_cos)
+        }
+        _ => {
+            let focus = params.sky.sun_focus;
+            let cos = direction.dot(params.light_dir).max(0.0);
+            (focus + 1.0) / (2.0 * std::f32::consts::PI) * cos.powf(focus)
+        }
+    };
+    sky_chance(params) * pdf
+}
+
+/// Picks a direction towards the sky with the density of [`sky_pdf`].
+pub fn sample_sky(params: &IParams, scene: &Scene, state: &mut Sampler) -> Vec3 {
+    match &scene.environment {
+        Some(map) if params.environment != 0 => sample_environment(map, &params.sky, state),
+        _ if params.sky.turbidity > 0.0 => {
+            let u = random_pair(state);
+            let cos_theta = 1.0 - u.x * sun_cone(&params.sky);
+            let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+            let phi = 2.0 * std::f32::consts::PI * u.y;
+            let (t, b) = orthonormal_basis(params.light_dir);
+            (t * phi.cos() + b * phi.sin()) * sin_theta + params.light_dir * cos_theta
+        }
+        _ => {
+            let focus = params.sky.sun_focus;
+            let u = random_pair(state);
+            let cos_theta = u.x.powf(1.0 / (focus + 1.0));
+            let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+            let phi = 2.0 * std::f32::consts::PI * u.y;
+            let (t, b) = orthonormal_basis(params.light_dir);
+            (t * phi.cos() + b * phi.sin()) * sin_theta + params.light_dir * cos_theta
+        }
+    }
+}
+
+/// Radiance of the environment map's texel towards `direction`.
+pub fn environment_light(direction: Vec3, map: &Environment, sky: &Sky) -> Vec3 {
+    let (x, y) = environment_texel(direction, map, sky);
+    map.pixels[y * map.width as usize + x] * sky.environment_intensity
+}
+
+/// The texel of the environment map, turned by the sky's rotation, towards `direction`.
+pub fn environment_texel(direction: Vec3, map: &Environment, sky: &Sky) -> (usize, usize) {
+    let uv = sphere_uv(Quat::from_rotation_y(-sky.environment_rotation) * direction);
+    let x = ((uv.x * map.width as f32) as usize).min(map.width as usize - 1);
+    let y = ((uv.y * map.height as f32) as usize).min(map.height as usize - 1);
+    (x, y)
+}
+
+/// Density of [`sample_environment`] picking `direction`: its texel's share of the map,
+/// spread over the solid angle the texel covers.
+pub fn environment_pdf(direction: Vec3, map: &Environment, sky: &Sky) -> f32 {
+    let sin_theta = (1.0 - direction.y * direction.y).max(0.0).sqrt();
+    if sin_theta <= 0.0 {
+        return 0.0;
+    }
+    let (x, y) = environment_texel(direction, map, sky);
+    let width = map.width as usize;
+    let row = &map.conditional_cdf[y * width..(y + 1) * width];
+    let probability = cdf_share(&map.marginal_cdf, y) * cdf_share(row, x);
+    let texels = (map.width * map.height) as f32;
+    probability * texels / (2.0 * std::f32::consts::PI * std::f32::consts::PI * sin_theta)
+}
+
+/// Picks a row and then a texel in it by their share of the map's weight, and a direction
+/// uniformly within the texel's latitude and longitude.
+pub fn sample_environment(map: &Environment, sky: &Sky, state: &mut Sampler) -> Vec3 {
+    let width = map.width as usize;
+    let random = random_pair(state);
+    let (y, v) = sample_cdf(&map.marginal_cdf, random.x);
+    let (x, u) = sample_cdf(&map.conditional_cdf[y * width..(y + 1) * width], random.y);
+    // the inverse of sphere_uv
+    let theta = std::f32::consts::PI * (y as f32 + v) / map.height as f32;
+    let phi = 2.0 * std::f32::consts::PI * (0.5 - (x as f32 + u) / map.width as f32);
+    let direction = Vec3::new(
+        theta.sin() * phi.cos(),
+        theta.cos(),
+        theta.sin() * phi.sin(),
+    );
+    Quat::from_rotation_y(sky.environment_rotation) * direction
+}
+
+/// Index of the entry in `cdf` that `u` falls in, and how far through its share it lies.
+pub fn sample_cdf(cdf: &[f32], u: f32) -> (usize, f32) {
+    let index = cdf.partition_point(|&c| c <= u).min(cdf.len() - 1);
+    let share = cdf_share(cdf, index);
+    let below = cdf[index] - share;
+    let t = if share > 0.0 {
+        ((u - below) / share).clamp(0.0, 1.0)
+    } else {
+        0.5
+    };
+    (index, t)
+}
+
+/// Probability of the entry at `index` of a cumulative distribution.
+fn cdf_share(cdf: &[f32], index: usize) -> f32 {
+    if index > 0 {
+        cdf[index] - cdf[index - 1]
+    } else {
+        cdf[0]
+    }
+}
+
+/// Power heuristic weight of a sample from the strategy with `pdf`, against one other
+/// strategy that could have produced it with `other_pdf`.
+pub fn mis_weight(pdf: f32, other_pdf: f32) -> f32 {
+    let ratio = other_pdf / pdf;
+    1.0 / (1.0 + ratio * ratio)
+}
+
+/// Applies the material's textures at the hit's uv.
+pub fn surface_material(material: Material, uv: Vec2, scene: &Scene) -> Material {
+    let sample_texture = |index: u32| texture::sample(&scene.textures[index as usize], uv);
+    let mut m = material;
+    if m.diffuse_texture != NO_TEXTURE {
+        m.diffuse_color *= srgb_to_linear(sample_texture(m.diffuse_texture).truncate());
+    }
+    if m.roughness_texture != NO_TEXTURE {
+        let texel = sample_texture(m.roughness_texture);
+        m.roughness *= texel.y;
+        m.metallic *= texel.z;
+    }
+    if m.emission_texture != NO_TEXTURE {
+        m.emission_color *= srgb_to_linear(sample_texture(m.emission_texture).truncate());
+    }
+    m
+}
+
+/// Bends the normal by a tangent-space normal map, if there's one and a tangent to
+/// orient it.
+pub fn normal_mapped(
+    normal: Vec3,
+    tangent: Vec4,
+    material: &Material,
+    uv: Vec2,
+    scene: &Scene,
+) -> Vec3 {
+    let t = tangent.truncate() - normal * normal.dot(tangent.truncate());
+    if material.normal_texture == NO_TEXTURE || t.length_squared() < 1e-12 {
+        return normal;
+    }
+    let tangent_dir = t.normalize();
+    let bitangent = normal.cross(tangent_dir) * tangent.w.signum();
+    let texture = &scene.textures[material.normal_texture as usize];
+    let m = texture::sample(texture, uv).truncate() * 2.0 - 1.0;
+    (tangent_dir * m.x + bitangent * m.y + normal * m.z).normalize()
+}
+
+pub fn srgb_to_linear(c: Vec3) -> Vec3 {
+    c.map(|c| {
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    })
+}
+
+pub fn reflect(i: Vec3, n: Vec3) -> Vec3 {
+    i - 2.0 * n.dot(i) * n
+}
+
+/// Bends `i` through a surface whose normal `n` faces it, `eta` being the ratio of the
+/// refractive indices on the incoming and outgoing side. Returns zero on total internal
+/// reflection.
+pub fn refract(i: Vec3, n: Vec3, eta: f32) -> Vec3 {
+    let cos_i = (-i).dot(n).clamp(0.0, 1.0);
+    let k = 1.0 - eta * eta * (1.0 - cos_i * cos_i);
+    if k < 0.0 {
+        Vec3::ZERO
+    } else {
+        eta * i + (eta * cos_i - k.sqrt()) * n
+    }
+}
+
+/// Beer-Lambert transmittance of `distance` travelled inside a dielectric.
+pub fn absorption(material: &Material, distance: f32) -> Vec3 {
+    material
+        .absorption_color
+        .max(Vec3::splat(1e-6))
+        .powf(material.absorption_density * distance)
+}
+
+/// Refractive index of a dielectric at `wavelength` in nm, or at the D line for 0.
+pub fn refractive_index(material: &Material, wavelength: f32) -> f32 {
+    let um = if wavelength > 0.0 { wavelength } else { D_LINE } / 1000.0;
+    let um2 = um * um;
+    if material.sellmeier_b != Vec3::ZERO {
+        let terms = material.sellmeier_b * um2 / (um2 - material.sellmeier_c);
+        (1.0 + terms.element_sum()).sqrt()
+    } else {
+        material.refractive_index + material.cauchy_b / um2
+    }
+}
+
+/// Value at `wavelength` of a smooth spectrum whose blue, green and red parts sum to one,
+/// so white stays flat and reflectances stay within 0..=1.
+pub fn rgb_to_spectrum(rgb: Vec3, wavelength: f32) -> f32 {
+    let blue = 1.0 - smoothstep(470.0, 520.0, wavelength);
+    let red = smoothstep(560.0, 610.0, wavelength);
+    rgb.x * red + rgb.y * (1.0 - red - blue) + rgb.z * blue
+}
+
+/// In spectral mode, the color's spectrum at the path's wavelength in every channel. RGB
+/// paths, with a wavelength of 0, keep the color.
+pub fn at_wavelength(rgb: Vec3, wavelength: f32) -> Vec3 {
+    if wavelength > 0.0 {
+        Vec3::splat(rgb_to_spectrum(rgb, wavelength))
+    } else {
+        rgb
+    }
+}
+
+/// Exact unpolarized Fresnel reflectance of a dielectric boundary, 1 on total internal
+/// reflection.
+pub fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let rs = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let rp = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    0.5 * (rs * rs + rp * rp)
+}
+
+/// Direction a ray leaves a surface in, and the throughput it carries divided by the
+/// probability of picking it. A zero weight absorbs the path.
+#[derive(Default, Debug, Copy, Clone)]
+pub struct BrdfSample {
+    pub direction: Vec3,
+    pub weight: Vec3,
+    /// Solid angle density of `direction`, for weighting it against light sampling.
+    pub pdf: f32,
+}
+
+/// The BRDF times the cosine towards a direction, and the solid angle density with which
+/// [`sample_brdf`] picks that direction.
+#[derive(Default, Debug, Copy, Clone)]
+pub struct BrdfEval {
+    pub value: Vec3,
+    pub pdf: f32,
+}
+
+/// Two tangents completing `n` to an orthonormal basis (Duff et al. 2017).
+pub fn orthonormal_basis(n: Vec3) -> (Vec3, Vec3) {
+    let s = if n.z >= 0.0 { 1.0 } else { -1.0 };
+    let a = -1.0 / (s + n.z);
+    let b = n.x * n.y * a;
+    (
+        Vec3::new(1.0 + s * n.x * n.x * a, s * b, -s * n.x),
+        Vec3::new(b, s + n.y * n.y * a, -n.y),
+    )
+}
+
+pub fn fresnel_schlick(f0: Vec3, cos_theta: f32) -> Vec3 {
+    f0 + (1.0 - f0) * (1.0 - cos_theta.clamp(0.0, 1.0)).powf(5.0)
+}
+
+/// Smith masking for one direction, `alpha2` being the squared GGX alpha.
+pub fn smith_g1(n_dot_x: f32, alpha2: f32) -> f32 {
+    2.0 * n_dot_x / (n_dot_x + (alpha2 + (1.0 - alpha2) * n_dot_x * n_dot_x).sqrt())
+}
+
+/// Height-correlated Smith masking-shadowing.
+pub fn smith_g2(n_dot_l: f32, n_dot_v: f32, alpha2: f32) -> f32 {
+    let l = n_dot_v * (alpha2 + (1.0 - alpha2) * n_dot_l * n_dot_l).sqrt();
+    let v = n_dot_l * (alpha2 + (1.0 - alpha2) * n_dot_v * n_dot_v).sqrt();
+    2.0 * n_dot_l * n_dot_v / (l + v)
+}
+
+/// Samples a microfacet normal from the GGX distribution of normals visible from `v`,
+/// both in the local frame where the surface normal is +Z (Heitz 2018).
+pub fn sample_ggx_vndf(v: Vec3, alpha: f32, u1: f32, u2: f32) -> Vec3 {
+    let vh = Vec3::new(alpha * v.x, alpha * v.y, v.z).normalize();
+    let len2 = vh.x * vh.x + vh.y * vh.y;
+    let t1 = if len2 > 0.0 {
+        Vec3::new(-vh.y, vh.x, 0.0) / len2.sqrt()
+    } else {
+        Vec3::X
+    };
+    let t2 = vh.cross(t1);
+    let r = u1.sqrt();
+    let phi = 2.0 * std::f32::consts::PI * u2;
+    let p1 = r * phi.cos();
+    let s = 0.5 * (1.0 + vh.z);
+    let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+    let nh = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * vh;
+    Vec3::new(alpha * nh.x, alpha * nh.y, nh.z.max(0.0)).normalize()
+}
+
+/// Chance of sampling the specular lobe rather than the diffuse one, by how much each
+/// would reflect head-on to the viewer.
+pub fn specular_chance(f0: Vec3, albedo: Vec3, n_dot_v: f32) -> f32 {
+    let fresnel = fresnel_schlick(f0, n_dot_v);
+    let specular_weight = fresnel.element_sum();
+    let diffuse_weight = (albedo * (1.0 - fresnel)).element_sum();
+    if specular_weight + diffuse_weight > 0.0 {
+        specular_weight / (specular_weight + diffuse_weight)
+    } else {
+        1.0
+    }
+}
+
+/// Samples the GGX specular lobe or the Lambertian base under it, picking between them
+/// by their estimated share of the reflected energy, and weights the direction by both.
+pub fn sample_brdf(
+    direction: Vec3,
+    normal: Vec3,
+    material: &Material,
+    state: &mut Sampler,
+) -> BrdfSample {
+    let alpha = (material.roughness * material.roughness).max(1e-4);
+    let v = -direction;
+    let n_dot_v = normal.dot(v).max(1e-4);
+    let (f0, albedo) = brdf_colors(material);
+
+    let l = if random_value(state) < specular_chance(f0, albedo, n_dot_v) {
+        let (t, b) = orthonormal_basis(normal);
+        let v_local = Vec3::new(v.dot(t), v.dot(b), n_dot_v);
+        let u = random_pair(state);
+        let h_local = sample_ggx_vndf(v_local, alpha, u.x, u.y);
+        reflect(
+            direction,
+            t * h_local.x + b * h_local.y + normal * h_local.z,
+        )
+    } else {
+        // the rare direction straight back into the surface cancels out
+        (normal + random_direction(state)).normalize_or(normal)
+    };
+    let eval = eval_brdf(direction, normal, material, l);
+    if eval.pdf <= 0.0 {
+        return BrdfSample::default();
+    }
+    BrdfSample {
+        direction: l,
+        weight: eval.value / eval.pdf,
+        pdf: eval.pdf,
+    }
+}
+
+/// GGX specular plus Lambertian diffuse, seen along `direction` and lit from `l`. Metals
+/// have no diffuse base, and their specular color is `diffuse_color`.
+pub fn eval_brdf(direction: Vec3, normal: Vec3, material: &Material, l: Vec3) -> BrdfEval {
+    let v = -direction;
+    let half = v + l;
+    let n_dot_l = normal.dot(l);
+    if n_dot_l <= 0.0 || half.length_squared() < 1e-12 {
+        return BrdfEval::default();
+    }
+    let h = half.normalize();
+    let alpha = (material.roughness * material.roughness).max(1e-4);
+    let alpha2 = alpha * alpha;
+    let n_dot_v = normal.dot(v).max(1e-4);
+    let n_dot_h = normal.dot(h).max(0.0);
+    let (f0, albedo) = brdf_colors(material);
+    let specular_chance = specular_chance(f0, albedo, n_dot_v);
+
+    // written so it doesn't cancel to zero for mirror-like surfaces
+    let d = alpha2 * n_dot_h * n_dot_h + (1.0 - n_dot_h * n_dot_h);
+    let distribution = alpha2 / (std::f32::consts::PI * d * d);
+    let specular =
+        fresnel_schlick(f0, v.dot(h)) * distribution * smith_g2(n_dot_l, n_dot_v, alpha2)
+            / (4.0 * n_dot_v);
+    let diffuse = albedo * (1.0 - fresnel_schlick(f0, n_dot_v)) * n_dot_l / std::f32::consts::PI;
+    // visible normals are reflected, and the diffuse lobe is cosine weighted
+    let specular_pdf = smith_g1(n_dot_v, alpha2) * distribution / (4.0 * n_dot_v);
+    let diffuse_pdf = n_dot_l / std::f32::consts::PI;
+    BrdfEval {
+        value: specular + diffuse,
+        pdf: specular_chance * specular_pdf + (1.0 - specular_chance) * diffuse_pdf,
+    }
+}
+
+/// Reflectance at normal incidence and diffuse albedo of a GGX material. Metals have no
+/// diffuse base, and their specular color is `diffuse_color`.
+pub fn brdf_colors(material: &Material) -> (Vec3, Vec3) {
+    let metallic = if material.kind() == MaterialKind::Metal {
+        1.0
+    } else {
+        material.metallic
+    };
+    (
+        Vec3::splat(0.04).lerp(material.diffuse_color, metallic),
+        material.diffuse_color * (1.0 - metallic),
+    )
+}
+
+/// Direction and distance from a point to a spot on a light, with the radiance it emits
+/// that way and the solid angle density of having picked it. A zero pdf means nothing
+/// was sampled.
+#[derive(Default, Debug, Copy, Clone)]
+pub struct LightSample {
+    pub direction: Vec3,
+    pub distance: f32,
+    pub emitted: Vec3,
+    pub pdf: f32,
+    /// From an [`AnalyticLight`], which BRDF samples never hit, so `emitted` is its
+    /// intensity and `pdf` only the chance of picking it.
+    pub analytic: bool,
+}
+
+/// Picks the sky by [`sky_chance`] and a direction towards it, or else a light by its
+/// share of the emitted power and a point on it: uniformly in the cone a sphere covers,
+/// uniformly by area on a triangle, or the one direction towards an analytic light.
+pub fn sample_light(
+    position: Vec3,
+    params: &IParams,
+    scene: &Scene,
+    lights: &[Light],
+    state: &mut Sampler,
+) -> LightSample {
+    let sky_chance = sky_chance(params);
+    let u = random_value(state);
+    // u can round up to 1, so don't rely on it to pick the sky when there's nothing else
+    if u < sky_chance || params.light_count == 0 {
+        let direction = sample_sky(params, scene, state);
+        return LightSample {
+            direction,
+            distance: NO_HIT,
+            emitted: sky_light(direction, params, scene),
+            pdf: sky_pdf(direction, params, scene),
+            analytic: false,
+        };
+    }
+    // reuse u to pick among the lights
+    let u = (u - sky_chance) / (1.0 - sky_chance);
+    let index = lights
+        .partition_point(|light| light.cdf <= u)
+        .min(lights.len() - 1);
+    let light = lights[index];
+    let Vec2 { x: u1, y: u2 } = random_pair(state);
+    let sphere_count = scene.spheres.len() as u32;
+
+    if light.object == ANALYTIC_LIGHT {
+        sample_analytic_light(
+            position,
+            &scene.lights[light.triangle as usize],
+            &scene.profiles,
+            (1.0 - sky_chance) * light.probability,
+            u1,
+            u2,
+        )
+    } else if light.object < sphere_count {
+        let sphere = &scene.spheres[light.object as usize];
+        let to_center = sphere.position - position;
+        let d2 = to_center.length_squared();
+        let r2 = sphere.radius * sphere.radius;
+        if d2 <= r2 {
+            return LightSample::default();
+        }
+        let sin2_max = r2 / d2;
+        let cos_max = (1.0 - sin2_max).max(0.0).sqrt();
+        // 1 - cos_max without cancellation for small or distant spheres
+        let cone = sin2_max / (1.0 + cos_max);
+        let cos_theta = 1.0 - u1 * cone;
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * std::f32::consts::PI * u2;
+        let w = to_center / d2.sqrt();
+        let (t, b) = orthonormal_basis(w);
+        let direction = (t * phi.cos() + b * phi.sin()) * sin_theta + w * cos_theta;
+        let hit = sphere_intersect(
+            Ray {
+                origin: position,
+                direction,
+            },
+            sphere,
+        );
+        if !hit.hit {
+            return LightSample::default();
+        }
+        let material = surface_material(sphere.material, hit.uv, scene);
+        LightSample {
+            direction,
+            distance: hit.distance,
+            emitted: material.emission_color * material.emission_strength,
+            pdf: (1.0 - sky_chance) * light.probability / (2.0 * std::f32::consts::PI * cone),
+            analytic: false,
+        }
+    } else {
+        let mesh = &scene.triangles[(light.object - sphere_count) as usize];
+        let [v0, v1, v2] = mesh.triangle(light.triangle as usize);
+        let su = u1.sqrt();
+        let (w0, w1) = (1.0 - su, u2 * su);
+        let w2 = 1.0 - w0 - w1;
+        let point = v0.position * w0 + v1.position * w1 + v2.position * w2;
+        let face = (v1.position - v0.position).cross(v2.position - v0.position);
+        let area = 0.5 * face.length();
+        let to_light = point - position;
+        let distance = to_light.length();
+        if area <= 0.0 || distance <= 0.0 {
+            return LightSample::default();
+        }
+        let direction = to_light / distance;
+        // one-sided lights only shine from their counter-clockwise side
+        let mut cos_light = -face.normalize().dot(direction);
+        if mesh.material.two_sided() {
+            cos_light = cos_light.abs();
+        }
+        if cos_light <= 1e-6 {
+            return LightSample::default();
+        }
+        let uv = v0.uv() * w0 + v1.uv() * w1 + v2.uv() * w2;
+        let material = surface_material(mesh.material, uv, scene);
+        LightSample {
+            direction,
+            distance,
+            emitted: material.emission_color * material.emission_strength,
+            pdf: (1.0 - sky_chance) * light.probability * distance * distance / (area * cos_light),
+            analytic: false,
+        }
+    }
+}
+
+/// The direction towards a point or spot light, or one uniformly within a directional
+/// light's angular radius, picked with `probability`.
+pub fn sample_analytic_light(
+    position: Vec3,
+    light: &AnalyticLight,
+    profiles: &[IesProfile],
+    probability: f32,
+    u1: f32,
+    u2: f32,
+) -> LightSample {
+    if light.kind == LightKind::Directional as u32 {
+        let cos_theta = 1.0 - u1 * (1.0 - light.cos_outer);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * std::f32::consts::PI * u2;
+        let w = -light.direction;
+        let (t, b) = orthonormal_basis(w);
+        return LightSample {
+            direction: (t * phi.cos() + b * phi.sin()) * sin_theta + w * cos_theta,
+            distance: NO_HIT,
+            emitted: light.intensity,
+            pdf: probability,
+            analytic: true,
+        };
+    }
+    let to_light = light.position - position;
+    let distance = to_light.length();
+    if distance <= 0.0 {
+        return LightSample::default();
+    }
+    let direction = to_light / distance;
+    let mut falloff = 1.0;
+    if light.kind == LightKind::Spot as u32 {
+        let cos_angle = (-direction).dot(light.direction);
+        let cos_inner = light.cos_inner.max(light.cos_outer + 1e-4);
+        falloff = smoothstep(light.cos_outer, cos_inner, cos_angle);
+    }
+    if let Some(profile) = profiles.get(light.profile as usize) {
+        falloff *= profile_intensity(profile, -direction, light.direction);
+    }
+    LightSample {
+        direction,
+        distance,
+        emitted: light.intensity * falloff / (distance * distance),
+        pdf: probability,
+        analytic: true,
+    }
+}
+
+/// Relative intensity of `profile` towards `direction`, interpolated between its samples.
+/// Vertical angles are measured from `axis`, horizontal ones around it from the first
+/// vector of [`orthonormal_basis`].
+pub fn profile_intensity(profile: &IesProfile, direction: Vec3, axis: Vec3) -> f32 {
+    let (t, b) = orthonormal_basis(axis);
+    let vertical = direction.dot(axis).clamp(-1.0, 1.0).acos();
+    let horizontal = direction.dot(b).atan2(direction.dot(t));
+    let columns = ies::VERTICAL_SAMPLES as f32;
+    let rows = ies::HORIZONTAL_SAMPLES as f32;
+    let x = vertical / std::f32::consts::PI * (columns - 1.0);
+    let y = (horizontal / (2.0 * std::f32::consts::PI)).rem_euclid(1.0) * rows;
+    let (x0, y0) = (x.floor(), y.floor());
+    let sample = |dx: f32, dy: f32| {
+        // vertical angles stop at the poles, horizontal ones go around
+        let column = (x0 + dx).min(columns - 1.0) as usize;
+        let row = (y0 + dy).rem_euclid(rows) as usize;
+        profile.intensity[row * ies::VERTICAL_SAMPLES as usize + column]
+    };
+    let (tx, ty) = (x - x0, y - y0);
+    let near = sample(0.0, 0.0) + (sample(1.0, 0.0) - sample(0.0, 0.0)) * tx;
+    let far = sample(0.0, 1.0) + (sample(1.0, 1.0) - sample(0.0, 1.0)) * tx;
+    near + (far - near) * ty
+}
+
+/// Solid angle density of light sampling from `origin` picking the point where `hit`,
+/// along `direction`, landed on an emissive object.
+pub fn light_pdf(
+    origin: Vec3,
+    direction: Vec3,
+    hit: &RayHit,
+    params: &IParams,
+    scene: &Scene,
+) -> f32 {
+    if params.light_power <= 0.0 {
+        return 0.0;
+    }
+    let chance = 1.0 - sky_chance(params);
+    let sphere_count = scene.spheres.len() as u32;
+
+    if hit.object < sphere_count {
+        let sphere = &scene.spheres[hit.object as usize];
+        let d2 = (sphere.position - origin).length_squared();
+        let r2 = sphere.radius * sphere.radius;
+        if d2 <= r2 {
+            return 0.0;
+        }
+        let sin2_max = r2 / d2;
+        let cone = sin2_max / (1.0 + (1.0 - sin2_max).max(0.0).sqrt());
+        let area = 4.0 * std::f32::consts::PI * r2;
+        let probability = lights::power(&sphere.material) * area / params.light_power;
+        chance * probability / (2.0 * std::f32::consts::PI * cone)
+    } else {
+        let mesh = &scene.triangles[(hit.object - sphere_count) as usize];
+        let [v0, v1, v2] = mesh.triangle(hit.triangle as usize).map(|v| v.position);
+        let cos_light = (v1 - v0)
+            .cross(v2 - v0)
+            .normalize_or_zero()
+            .dot(direction)
+            .abs();
+        if cos_light <= 1e-6 {
+            return 0.0;
+        }
+        // the triangle's area cancels out of its probability and its density over it
+        chance * lights::power(&mesh.material) * hit.distance * hit.distance
+            / (params.light_power * cos_light)
+    }
+}
+
+/// Whether nothing blocks the segment from a surface towards a sampled light.
+pub fn unoccluded(
+    position: Vec3,
+    normal: Vec3,
+    sample: &LightSample,
+    scene: &Scene,
+    tlas: &Tlas,
+) -> bool {
+    let shadow = calculate_collision(
+        Ray {
+            origin: position + normal * RAY_OFFSET,
+            direction: sample.direction,
+        },
+        scene,
+        tlas,
+    );
+    !shadow.hit || shadow.distance >= sample.distance * 0.999 - RAY_OFFSET
+}
+
+/// Averages the paths of a pixel's rays this frame, each jittered away from `camera_ray`
+/// by its own sample.
+pub fn trace(
+    camera_ray: Ray,
+    params: &IParams,
+    scene: &Scene,
+    tlas: &Tlas,
+    lights: &[Light],
+    state: &mut Sampler,
+) -> Vec3 {
+    let mut total_light = Vec3::ZERO;
+    for _ in 0..RAYS_PER_PIXEL {
+        let ray = Ray {
+            origin: camera_ray.origin,
+            direction: (camera_ray.direction + random_direction(state) * 0.001).normalize(),
+        };
+        total_light += trace_single(ray, params, scene, tlas, lights, state);
+        next_sample(state);
+    }
+    total_light / RAYS_PER_PIXEL as f32
+}
+
+pub fn trace_single(
+    mut ray: Ray,
+    params: &IParams,
+    scene: &Scene,
+    tlas: &Tlas,
+    lights: &[Light],
+    state: &mut Sampler,
+) -> Vec3 {
+    let mut light = Vec3::ZERO;
+    let mut color = Vec3::ONE;
+    // density of the last bounce, to weigh what it hits against light sampling. zero for
+    // camera rays and dielectric bounces, which light sampling can't reproduce
+    let mut last_pdf = 0.0;
+    let wavelength = if params.spectral != 0 {
+        WAVELENGTH_MIN + (WAVELENGTH_MAX - WAVELENGTH_MIN) * random_value(state)
+    } else {
+        0.0
+    };
+
+    for _ in 0..MAX_BOUNCES {
+        let mut hit = calculate_collision(ray, scene, tlas);
+        if hit.hit {
+            hit.material = surface_material(hit.material, hit.uv, scene);
+            hit.normal = normal_mapped(hit.normal, hit.tangent, &hit.material, hit.uv, scene);
+            if hit.is_backface && hit.material.kind() == MaterialKind::Dielectric {
+                color *= at_wavelength(absorption(&hit.material, hit.distance), wavelength);
+            }
+            let emitted = hit.material.emission_color * hit.material.emission_strength;
+            if emitted != Vec3::ZERO {
+                let weight = if last_pdf > 0.0 {
+                    mis_weight(
+                        last_pdf,
+                        light_pdf(ray.origin, ray.direction, &hit, params, scene),
+                    )
+                } else {
+                    1.0
+                };
+                light += color * at_wavelength(emitted, wavelength) * weight;
+            }
+
+            match hit.material.kind() {
+                MaterialKind::Diffuse | MaterialKind::Metal => {
+                    if params.light_count > 0 || sky_chance(params) > 0.0 {
+                        let light_sample = sample_light(hit.position, params, scene, lights, state);
+                        let brdf = eval_brdf(
+                            ray.direction,
+                            hit.normal,
+                            &hit.material,
+                            light_sample.direction,
+                        );
+                        let weight = if light_sample.analytic {
+                            1.0
+                        } else {
+                            mis_weight(light_sample.pdf, brdf.pdf)
+                        };
+                        if light_sample.pdf > 0.0
+                            && brdf.pdf > 0.0
+                            && unoccluded(hit.position, hit.normal, &light_sample, scene, tlas)
+                        {
+                            light += color
+                                * at_wavelength(brdf.value, wavelength)
+                                * at_wavelength(light_sample.emitted, wavelength)
+                                * (weight / light_sample.pdf);
+                        }
+                    }
+
+                    let sample = sample_brdf(ray.direction, hit.normal, &hit.material, state);
+                    if sample.weight == Vec3::ZERO {
+                        break;
+                    }
+                    ray.direction = sample.direction;
+                    color *= at_wavelength(sample.weight, wavelength);
+                    last_pdf = sample.pdf;
+                }
+                MaterialKind::Dielectric => {
+                    // the outside is assumed to be air
+                    let index = refractive_index(&hit.material, wavelength);
+                    let eta = if hit.is_backface { index } else { 1.0 / index };
+                    let cos_i = (-ray.direction).dot(hit.normal).clamp(0.0, 1.0);
+                    ray.direction = if random_value(state) < fresnel_dielectric(cos_i, eta) {
+                        reflect(ray.direction, hit.normal)
+                    } else {
+                        refract(ray.direction, hit.normal, eta)
+                    };
+                    last_pdf = 0.0;
+                }
+                MaterialKind::Emissive => break,
+            }
+            // start on the side of the surface the ray leaves from
+            let side = if ray.direction.dot(hit.normal) >= 0.0 {
+                1.0
+            } else {
+                -1.0
+            };
+            ray.origin = hit.position + hit.normal * RAY_OFFSET * side;
+        } else {
+            let sky_weight = if last_pdf > 0.0 {
+                mis_weight(last_pdf, sky_pdf(ray.direction, params, scene))
+            } else {
+                1.0
+            };
+            let environment = get_environment_light(ray, params, scene, sky_weight);
+            light += at_wavelength(environment, wavelength) * color;
+            break;
+        }
+    }
+
+    if wavelength > 0.0 {
+        wavelength_to_rgb(wavelength) * light.x
+    } else {
+        light
+    }
+}
+
+/// Walks `tlas`, which must have been built from `scene`, down to the objects in its leaves.
+pub fn calculate_collision(ray: Ray, scene: &Scene, tlas: &Tlas) -> RayHit {
+    let mut closest_hit = RayHit::default();
+    if tlas.is_empty() {
+        return closest_hit;
+    }
+    let sphere_count = scene.spheres.len() as u32;
+    let mut stack = Vec::with_capacity(crate::bvh::MAX_DEPTH);
+    stack.push(0);
+
+    while let Some(index) = stack.pop() {
+        let node = &tlas.nodes[index as usize];
+        if aabb_distance(ray, node.min, node.max) >= closest_distance(&closest_hit) {
+            continue;
+        }
+
+        if node.count > 0 {
+            for j in node.left_first..node.left_first + node.count {
+                let object = tlas.objects[j as usize];
+                if object < sphere_count {
+                    let hit = sphere_intersect(ray, &scene.spheres[object as usize]);
+                    if hit.hit && hit.distance < closest_distance(&closest_hit) {
+                        closest_hit = hit;
+                        closest_hit.object = object;
+                    }
+                } else {
+                    let tri_mesh = &scene.triangles[(object - sphere_count) as usize];
+                    mesh_intersect(ray, tri_mesh, object, &mut closest_hit);
+                }
+            }
+        } else {
+            push_children(ray, &tlas.nodes, node, &mut stack);
+        }
+    }
+    closest_hit
+}
+
+pub fn mesh_intersect(ray: Ray, tri_mesh: &TriangleMesh, object: u32, closest_hit: &mut RayHit) {
+    if tri_mesh.indices.is_empty() {
+        return;
+    }
+    let mut stack = Vec::with_capacity(crate::bvh::MAX_DEPTH);
+    stack.push(0);
+
+    while let Some(index) = stack.pop() {
+        let node = &tri_mesh.bvh[index as usize];
+        if aabb_distance(ray, node.min, node.max) >= closest_distance(closest_hit) {
+            continue;
+        }
+
+        if node.count > 0 {
+            for j in node.left_first..node.left_first + node.count {
+                let hit =
+                    triangle_intersect(ray, tri_mesh, j as usize, tri_mesh.material.two_sided());
+                if hit.hit && hit.distance < closest_distance(closest_hit) {
+                    *closest_hit = hit;
+                    closest_hit.object = object;
+                    closest_hit.triangle = j;
+                    closest_hit.material = tri_mesh.material;
+                }
+            }
+        } else {
+            push_children(ray, &tri_mesh.bvh, node, &mut stack);
+        }
+    }
+}
+
+/// Pushes the nearer child last so it's visited first and the farther one can be culled.
+fn push_children(ray: Ray, nodes: &[BvhNode], node: &BvhNode, stack: &mut Vec<u32>) {
+    let left = &nodes[node.left_first as usize];
+    let right = &nodes[node.left_first as usize + 1];
+    if aabb_distance(ray, left.min, left.max) < aabb_distance(ray, right.min, right.max) {
+        stack.extend([node.left_first + 1, node.left_first]);
+    } else {
+        stack.extend([node.left_first, node.left_first + 1]);
+    }
+}
+
+fn closest_distance(hit: &RayHit) -> f32 {
+    if hit.hit { hit.distance } else { NO_HIT }
+}
+
+/// Distance to the box along the ray, or `NO_HIT` if it's missed.
+pub fn aabb_distance(ray: Ray, aabb_min: Vec3, aabb_max: Vec3) -> f32 {
+    let inv_dir = 1.0 / ray.direction;
+    let t1 = (aabb_min - ray.origin) * inv_dir;
+    let t2 = (aabb_max - ray.origin) * inv_dir;
+
+    let tmin = t1.min(t2).max_element();
+    let tmax = t1.max(t2).min_element();
+
+    if tmax >= tmin.max(0.0) {
+        tmin.max(0.0)
+    } else {
+        NO_HIT
+    }
+}
+
+pub fn sphere_intersect(ray: Ray, sphere: &Sphere) -> RayHit {
+    let mut hit = RayHit::default();
+    let oc = ray.origin - sphere.position;
+    let a = ray.direction.dot(ray.direction);
+    let b = 2.0 * oc.dot(ray.direction);
+    let c = oc.dot(oc) - sphere.radius * sphere.radius;
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant >= 0.0 {
+        // rays starting inside the sphere hit its far side
+        let mut t = (-b - discriminant.sqrt()) / (2.0 * a);
+        if t <= 1e-5 {
+            t = (-b + discriminant.sqrt()) / (2.0 * a);
+        }
+        if t > 1e-5 {
+            hit.distance = t;
+            hit.material = sphere.material;
+            hit.position = ray.origin + t * ray.direction;
+            let outward = (hit.position - sphere.position).normalize();
+            hit.is_backface = ray.direction.dot(outward) > 0.0;
+            let facing = if hit.is_backface { -1.0 } else { 1.0 };
+            hit.normal = outward * facing;
+            hit.uv = sphere_uv(outward);
+            hit.tangent = sphere_tangent(outward) * facing;
+            hit.hit = true;
+        }
+    }
+    hit
+}
+
+/// Longitude and latitude of the outward normal, with v = 0 at the top.
+pub fn sphere_uv(n: Vec3) -> Vec2 {
+    Vec2::new(
+        0.5 - n.z.atan2(n.x) / (2.0 * std::f32::consts::PI),
+        n.y.clamp(-1.0, 1.0).acos() / std::f32::consts::PI,
+    )
+}
+
+/// Points along increasing u, around the y axis. Zero at the poles.
+pub fn sphere_tangent(n: Vec3) -> Vec4 {
+    let t = Vec3::new(n.z, 0.0, -n.x);
+    if t.length_squared() < 1e-12 {
+        return Vec4::ZERO;
+    }
+    t.normalize().extend(1.0)
+}
+
+// moller-trumbore algorithm
+pub fn triangle_intersect(
+    ray: Ray,
+    tri_mesh: &TriangleMesh,
+    triangle: usize,
+    two_sided: bool,
+) -> RayHit {
+    let mut hit = RayHit::default();
+    let [vert0, vert1, vert2] = tri_mesh.triangle(triangle);
+    let v0 = vert0.position;
+    let v1 = vert1.position;
+    let v2 = vert2.position;
+
+    let edge1 = v1 - v0;
+    let edge2 = v2 - v0;
+    let h = ray.direction.cross(edge2);
+    let a = edge1.dot(h);
+
+    if a.abs() < 0.0001 {
+        return hit;
+    }
+
+    let f = 1.0 / a;
+    let s = ray.origin - v0;
+    let u = f * s.dot(h);
+
+    if !(0.0..=1.0).contains(&u) {
+        return hit;
+    }
+
+    let q = s.cross(edge1);
+    let v = f * ray.direction.dot(q);
+
+    if v < 0.0 || u + v > 1.0 {
+        return hit;
+    }
+
+    let w = 1.0 - u - v;
+
+    let t = f * edge2.dot(q);
+
+    let tri_face_vector = edge1.cross(edge2);
+    let determinant = tri_face_vector.dot(ray.direction);
+    // one-sided triangles are only hit from the side their vertices wind counter-clockwise on
+    let is_valid = if two_sided {
+        determinant.abs() >= 1e-8
+    } else {
+        determinant <= -1e-8
+    };
+
+    hit.hit = is_valid && t > 1e-5 && u >= 0.0 && v >= 0.0 && w >= 0.0;
+    hit.normal = tri_face_vector.normalize() * -determinant.signum();
+    // interpolated normals are kept on the same side as the face normal we hit, and the
+    // tangent frame is flipped with them
+    let smooth_normal = vert0.normal * w + vert1.normal * u + vert2.normal * v;
+    let has_smooth_normal = smooth_normal.length_squared() > 1e-12;
+    let outward = if has_smooth_normal {
+        smooth_normal.normalize()
+    } else {
+        tri_face_vector.normalize()
+    };
+    let facing = if outward.dot(hit.normal) >= 0.0 {
+        1.0
+    } else {
+        -1.0
+    };
+    if !tri_mesh.flat_shading && has_smooth_normal {
+        hit.normal = outward * facing;
+    }
+    hit.tangent = (vert0.tangent * w + vert1.tangent * u + vert2.tangent * v) * facing;
+    hit.uv = vert0.uv() * w + vert1.uv() * u + vert2.uv() * v;
+    hit.distance = t;
+    hit.position = ray.origin + t * ray.direction;
+    hit.is_backface = determinant > 0.0;
+    hit
+}
+
+/// Traces one pixel the same way the compute shader's `main` entry point does.
+pub fn render_pixel(
+    x: u32,
+    y: u32,
+    params: &IParams,
+    scene: &Scene,
+    tlas: &Tlas,
+    lights: &[Light],
+) -> Vec3 {
+    let frag_coord =
+        Vec2::new(x as f32, y as f32) / Vec2::new(params.width as f32, params.height as f32);
+
+    let mut state = pixel_sampler(UVec2::new(x, y), frag_coord, params);
+
+    let aspect_ratio = params.width as f32 / params.height as f32;
+    let half_fov_tan = (60.0f32.to_radians() * 0.5).tan();
+    let px = (2.0 * frag_coord.x - 1.0) * half_fov_tan * aspect_ratio;
+    let py = (1.0 - 2.0 * frag_coord.y) * half_fov_tan;
+    let ray_dir = (params.camera_dir * Vec3A::new(px, py, -1.0)).to_vec3();
+    let ray = Ray {
+        origin: params.camera_pos,
+        direction: ray_dir,
+    };
+    trace(ray, params, scene, tlas, lights, &mut state) * params.exposure.exp2()
+}
+
+/// Renders `frames` passes across all available threads and returns the averaged,
+/// unclamped linear radiance in row-major order. `params.random_seed` and
+/// `params.accumulated_frames` are advanced per frame; the other fields are used as-is.
+pub fn render(params: &IParams, scene: &Scene, frames: u32) -> Vec<Vec3> {
+    let width = params.width as usize;
+    let height = params.height as usize;
+    let mut pixels = vec![Vec3::ZERO; width * height];
+    if pixels.is_empty() {
+        return pixels;
+    }
+
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let rows_per_chunk = height.div_ceil(threads);
+    let seeds = (0..frames).map(|_| rand::random()).collect::<Vec<f32>>();
+    let tlas = Tlas::new(&scene.spheres, &scene.triangles);
+    let (lights, light_power) = lights::build(
+        &scene.spheres,
+        &scene.triangles,
+        &scene.lights,
+        &scene.profiles,
+    );
+    let params = &IParams {
+        light_count: lights.len() as u32,
+        light_power,
+        environment: scene.environment.is_some() as u32,
+        ..*params
+    };
+
+    std::thread::scope(|s| {
+        for (chunk_index, chunk) in pixels.chunks_mut(rows_per_chunk * width).enumerate() {
+            let seeds = &seeds;
+            let tlas = &tlas;
+            let lights = &lights;
+            s.spawn(move || {
+                for (i, pixel) in chunk.iter_mut().enumerate() {
+                    let x = (i % width) as u32;
+                    let y = (chunk_index * rows_per_chunk + i / width) as u32;
+                    for (frame, &random_seed) in seeds.iter().enumerate() {
+                        let params = IParams {
+                            random_seed,
+                            accumulated_frames: frame as u32,
+                            ..*params
+                        };
+                        *pixel += render_pixel(x, y, &params, scene, tlas, lights);
+                    }
+                    *pixel /= frames.max(1) as f32;
+                }
+            });
+        }
+    });
+
+    pixels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Plain pseudo-random numbers, independent of any pixel or sample index.
+    fn random_sampler(seed: u32) -> Sampler {
+        Sampler {
+            kind: SamplerKind::Random as u32,
+            seed,
+            pixel: UVec2::ZERO,
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    fn ray(origin: Vec3, direction: Vec3) -> Ray {
+        Ray { origin, direction }
+    }
+
+    fn unit_sphere() -> Sphere {
+        Sphere {
+            position: Vec3::ZERO,
+            radius: 1.0,
+            material: Material::default(),
+        }
+    }
+
+    /// One triangle in the z = 0 plane, counter-clockwise seen from +Z.
+    fn triangle() -> TriangleMesh {
+        TriangleMesh::new(vec![Vec3::ZERO, Vec3::X, Vec3::Y], Material::default())
+    }
+
+    #[test]
+    fn sphere_from_outside_hits_near_side() {
+        let hit = sphere_intersect(ray(Vec3::new(0.0, 0.0, 5.0), Vec3::NEG_Z), &unit_sphere());
+        assert!(hit.hit);
+        assert!((hit.distance - 4.0).abs() < 1e-5);
+        assert!(!hit.is_backface);
+        assert!(hit.normal.abs_diff_eq(Vec3::Z, 1e-5));
+    }
+
+    #[test]
+    fn sphere_from_inside_hits_far_side() {
+        let hit = sphere_intersect(ray(Vec3::ZERO, Vec3::NEG_Z), &unit_sphere());
+        assert!(hit.hit);
+        assert!((hit.distance - 1.0).abs() < 1e-5);
+        assert!(hit.is_backface);
+        // flipped to face the ray
+        assert!(hit.normal.abs_diff_eq(Vec3::Z, 1e-5));
+    }
+
+    #[test]
+    fn sphere_behind_ray_is_missed() {
+        let hit = sphere_intersect(ray(Vec3::new(0.0, 0.0, 5.0), Vec3::Z), &unit_sphere());
+        assert!(!hit.hit);
+    }
+
+    #[test]
+    fn triangle_two_sided_hits_both_sides() {
+        let front = triangle_intersect(
+            ray(Vec3::new(0.2, 0.2, 1.0), Vec3::NEG_Z),
+            &triangle(),
+            0,
+            true,
+        );
+        assert!(front.hit);
+        assert!(!front.is_backface);
+        assert!((front.distance - 1.0).abs() < 1e-5);
+        assert!(front.normal.abs_diff_eq(Vec3::Z, 1e-5));
+
+        let back = triangle_intersect(
+            ray(Vec3::new(0.2, 0.2, -1.0), Vec3::Z),
+            &triangle(),
+            0,
+            true,
+        );
+        assert!(back.hit);
+        assert!(back.is_backface);
+        // flipped to face the ray
+        assert!(back.normal.abs_diff_eq(Vec3::NEG_Z, 1e-5));
+    }
+
+    #[test]
+    fn triangle_one_sided_hits_only_its_counter_clockwise_side() {
+        let front = ray(Vec3::new(0.2, 0.2, 1.0), Vec3::NEG_Z);
+        assert!(triangle_intersect(front, &triangle(), 0, false).hit);
+        let back = ray(Vec3::new(0.2, 0.2, -1.0), Vec3::Z);
+        assert!(!triangle_intersect(back, &triangle(), 0, false).hit);
+    }
+
+    #[test]
+    fn triangle_outside_edges_is_missed() {
+        let hit = triangle_intersect(
+            ray(Vec3::new(0.8, 0.8, 1.0), Vec3::NEG_Z),
+            &triangle(),
+            0,
+            true,
+        );
+        assert!(!hit.hit);
+    }
+
+    #[test]
+    fn aabb_missed() {
+        let distance = aabb_distance(
+            ray(Vec3::new(0.0, 5.0, 5.0), Vec3::NEG_Z),
+            Vec3::splat(-1.0),
+            Vec3::ONE,
+        );
+        assert_eq!(distance, NO_HIT);
+        let behind = aabb_distance(
+            ray(Vec3::new(0.0, 0.0, 5.0), Vec3::Z),
+            Vec3::splat(-1.0),
+            Vec3::ONE,
+        );
+        assert_eq!(behind, NO_HIT);
+    }
+
+    #[test]
+    fn aabb_hit_from_outside_and_inside() {
+        let outside = aabb_distance(
+            ray(Vec3::new(0.0, 0.0, 5.0), Vec3::NEG_Z),
+            Vec3::splat(-1.0),
+            Vec3::ONE,
+        );
+        assert!((outside - 4.0).abs() < 1e-5);
+        let inside = aabb_distance(ray(Vec3::ZERO, Vec3::X), Vec3::splat(-1.0), Vec3::ONE);
+        assert_eq!(inside, 0.0);
+    }
+
+    fn random_point(state: &mut Sampler, extent: f32) -> Vec3 {
+        (Vec3::new(
+            random_value(state),
+            random_value(state),
+            random_value(state),
+        ) * 2.0
+            - 1.0)
+            * extent
+    }
+
+    /// Spheres and meshes of scattered triangles, overlapping each other in a box.
+    fn random_scene(state: &mut Sampler) -> Scene {
+        let spheres = (0..20)
+            .map(|_| Sphere {
+                position: random_point(state, 5.0),
+                radius: 0.2 + random_value(state),
+                material: Material::default(),
+            })
+            .collect();
+        let triangles = (0..5)
+            .map(|_| {
+                let center = random_point(state, 4.0);
+                let vertices = (0..30).map(|_| center + random_point(state, 1.5)).collect();
+                TriangleMesh::new(vertices, Material::default())
+            })
+            .collect();
+        Scene {
+            spheres,
+            triangles,
+            ..Default::default()
+        }
+    }
+
+    /// Closest hit by testing every sphere and triangle.
+    fn brute_force(ray: Ray, scene: &Scene) -> RayHit {
+        let spheres = scene
+            .spheres
+            .iter()
+            .map(|sphere| sphere_intersect(ray, sphere));
+        let triangles = scene.triangles.iter().flat_map(|mesh| {
+            (0..mesh.indices.len() / 3).map(|i| triangle_intersect(ray, mesh, i, true))
+        });
+        spheres
+            .chain(triangles)
+            .filter(|hit| hit.hit)
+            .min_by(|a, b| a.distance.total_cmp(&b.distance))
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn tlas_finds_the_same_closest_hit_as_brute_force() {
+        let mut state = random_sampler(12345);
+        let scene = random_scene(&mut state);
+        let tlas = Tlas::new(&scene.spheres, &scene.triangles);
+        let mut hits = 0;
+        for _ in 0..2000 {
+            let origin = random_direction(&mut state) * 12.0;
+            let target = random_point(&mut state, 5.0);
+            let ray = ray(origin, (target - origin).normalize());
+            let expected = brute_force(ray, &scene);
+            let hit = calculate_collision(ray, &scene, &tlas);
+            assert_eq!(hit.hit, expected.hit, "{ray:?}");
+            if hit.hit {
+                assert!((hit.distance - expected.distance).abs() < 1e-4, "{ray:?}");
+                assert!(hit.normal.abs_diff_eq(expected.normal, 1e-4), "{ray:?}");
+                hits += 1;
+            }
+        }
+        // aimed into the clutter, plenty of rays hit something
+        assert!(hits > 500, "{hits}");
+    }
+
+    fn white_metal(roughness: f32) -> Material {
+        Material {
+            diffuse_color: Vec3::ONE,
+            roughness,
+            kind: MaterialKind::Metal as u32,
+            ..Default::default()
+        }
+    }
+
+    /// GGX distribution of normals, for checking the sampled weights against the BRDF.
+    fn ggx_d(n_dot_h: f32, alpha2: f32) -> f32 {
+        let d = n_dot_h * n_dot_h * (alpha2 - 1.0) + 1.0;
+        alpha2 / (std::f32::consts::PI * d * d)
+    }
+
+    #[test]
+    fn vndf_sampling_matches_the_ggx_brdf() {
+        let roughness = 0.5f32;
+        let alpha2 = roughness.powi(4);
+        let v = Vec3::new(0.6, 0.0, 0.8);
+        let samples = 200_000;
+        let mut state = random_sampler(7);
+
+        // integrals of brdf * cos and of brdf * cos * l.x, once importance sampled...
+        let (mut sampled, mut sampled_x) = (0.0, 0.0);
+        for _ in 0..samples {
+            let sample = sample_brdf(-v, Vec3::Z, &white_metal(roughness), &mut state);
+            sampled += sample.weight.x;
+            sampled_x += sample.weight.x * sample.direction.x;
+        }
+        // ...and once over uniformly picked directions of the hemisphere
+        let (mut uniform, mut uniform_x) = (0.0, 0.0);
+        for _ in 0..samples {
+            let mut l = random_direction(&mut state);
+            l.z = l.z.abs();
+            let h = (l + v).normalize();
+            let brdf = ggx_d(h.z, alpha2) * smith_g2(l.z, v.z, alpha2) / (4.0 * l.z * v.z);
+            let weight = brdf * l.z * 2.0 * std::f32::consts::PI;
+            uniform += weight;
+            uniform_x += weight * l.x;
+        }
+
+        let n = samples as f32;
+        let (sampled, sampled_x, uniform, uniform_x) =
+            (sampled / n, sampled_x / n, uniform / n, uniform_x / n);
+        assert!((sampled - uniform).abs() < 0.02, "{sampled} != {uniform}");
+        assert!(
+            (sampled_x - uniform_x).abs() < 0.02,
+            "{sampled_x} != {uniform_x}"
+        );
+    }
+
+    #[test]
+    fn white_furnace_does_not_gain_energy() {
+        let sky = Sky {
+            ground_color: Vec3::ONE,
+            horizon_color: Vec3::ONE,
+            zenith_color: Vec3::ONE,
+            sun_intensity: 0.0,
+            ..Default::default()
+        };
+        let params = IParams {
+            light_dir: Vec3::Y,
+            sky,
+            ..Default::default()
+        };
+        let white_diffuse = Material {
+            diffuse_color: Vec3::ONE,
+            roughness: 1.0,
+            ..Default::default()
+        };
+        // a mirror returns everything, rough lobes lose what single scattering misses
+        for (material, least) in [
+            (white_metal(0.0), 0.999),
+            (white_metal(0.6), 0.75),
+            (white_diffuse, 0.85),
+        ] {
+            let scene = Scene {
+                spheres: vec![Sphere {
+                    material,
+                    ..unit_sphere()
+                }],
+                sky,
+                ..Default::default()
+            };
+            let tlas = Tlas::new(&scene.spheres, &scene.triangles);
+            let mut state = random_sampler(3);
+            let samples = 20_000;
+            let mut total = Vec3::ZERO;
+            for _ in 0..samples {
+                let target = random_direction(&mut state) * 0.5;
+                let origin = Vec3::new(0.0, 0.0, 5.0);
+                let ray = ray(origin, (target - origin).normalize());
+                total += trace_single(ray, &params, &scene, &tlas, &[], &mut state);
+            }
+            let mean = total / samples as f32;
+            assert!(mean.max_element() <= 1.001, "{material:?} {mean}");
+            assert!(mean.min_element() >= least, "{material:?} {mean}");
+        }
+    }
+
+    #[test]
+    fn fresnel_at_normal_incidence() {
+        // ((n - 1) / (n + 1))^2 for glass
+        let reflectance = fresnel_dielectric(1.0, 1.0 / 1.5);
+        assert!((reflectance - 0.04).abs() < 1e-4);
+        assert!(fresnel_dielectric(1.0, 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn fresnel_total_internal_reflection() {
+        // leaving glass beyond the critical angle of about 41.8°
+        let cos_i = 60.0f32.to_radians().cos();
+        assert_eq!(fresnel_dielectric(cos_i, 1.5), 1.0);
+        assert!(fresnel_dielectric(1.0, 1.5) < 1.0);
+    }
+
+    #[test]
+    fn fresnel_grows_towards_grazing() {
+        let eta = 1.0 / 1.5;
+        assert!(fresnel_dielectric(0.1, eta) > fresnel_dielectric(0.9, eta));
+        assert!(fresnel_dielectric(0.0, eta) > 0.99);
+    }
+
+    #[test]
+    fn absorption_follows_beer_lambert() {
+        let material = Material {
+            kind: MaterialKind::Dielectric as u32,
+            absorption_color: Vec3::new(0.5, 0.25, 1.0),
+            absorption_density: 2.0,
+            ..Default::default()
+        };
+        // the color is reached after 1 / density, so 0.5^(2 * 1.5) after 1.5 units
+        let transmittance = absorption(&material, 1.5);
+        assert!(transmittance.abs_diff_eq(Vec3::new(0.125, 0.015625, 1.0), 1e-6));
+        assert_eq!(absorption(&material, 0.0), Vec3::ONE);
+        // and twice the distance is the square
+        let twice = absorption(&material, 3.0);
+        assert!(twice.abs_diff_eq(transmittance * transmittance, 1e-6));
+    }
+
+    #[test]
+    fn sellmeier_matches_known_glasses() {
+        // Schott N-BK7, 1.5168 at the helium d line
+        let bk7 = Material {
+            kind: MaterialKind::Dielectric as u32,
+            sellmeier_b: Vec3::new(1.039_612, 0.231_792_3, 1.010_469_4),
+            sellmeier_c: Vec3::new(0.006_000_7, 0.020_017_9, 103.560_65),
+            ..Default::default()
+        };
+        assert!((refractive_index(&bk7, 587.56) - 1.5168).abs() < 1e-4);
+        assert!((refractive_index(&bk7, 486.13) - 1.5224).abs() < 1e-4);
+        // normal dispersion, blue bends more than red
+        assert!(refractive_index(&bk7, 400.0) > refractive_index(&bk7, 700.0));
+    }
+
+    #[test]
+    fn cauchy_adds_to_the_constant_index() {
+        let flint = Material {
+            kind: MaterialKind::Dielectric as u32,
+            refractive_index: 1.6,
+            cauchy_b: 0.02,
+            ..Default::default()
+        };
+        assert!((refractive_index(&flint, 500.0) - 1.68).abs() < 1e-5);
+        assert_eq!(
+            refractive_index(&flint, 0.0),
+            refractive_index(&flint, D_LINE)
+        );
+    }
+
+    /// Solid angle of a triangle seen from the origin (Van Oosterom and Strackee 1983).
+    fn solid_angle(a: Vec3, b: Vec3, c: Vec3) -> f32 {
+        let (la, lb, lc) = (a.length(), b.length(), c.length());
+        let numerator = a.dot(b.cross(c)).abs();
+        let denominator = la * lb * lc + a.dot(b) * lc + a.dot(c) * lb + b.dot(c) * la;
+        2.0 * numerator.atan2(denominator)
+    }
+
+    fn emissive() -> Material {
+        Material {
+            emission_color: Vec3::ONE,
+            emission_strength: 1.0,
+            kind: MaterialKind::Emissive as u32,
+            ..Default::default()
+        }
+    }
+
+    /// Parameters sampling only `scene`'s lights, without a sun.
+    fn light_params(scene: &Scene) -> (IParams, Vec<Light>) {
+        let (lights, light_power) = crate::lights::build(
+            &scene.spheres,
+            &scene.triangles,
+            &scene.lights,
+            &scene.profiles,
+        );
+        let params = IParams {
+            light_count: lights.len() as u32,
+            light_power,
+            sky: Sky {
+                sun_intensity: 0.0,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        (params, lights)
+    }
+
+    #[test]
+    fn triangle_light_pdf_covers_its_solid_angle() {
+        let corners = [
+            Vec3::new(-1.0, 2.0, -1.0),
+            Vec3::new(2.0, 2.0, -1.0),
+            Vec3::new(-1.0, 3.0, 2.0),
+        ];
+        let scene = Scene {
+            triangles: vec![TriangleMesh::new(corners.to_vec(), emissive())],
+            ..Default::default()
+        };
+        let (params, lights) = light_params(&scene);
+
+        // with a single light, 1 / pdf averages to the solid angle it was picked from
+        let mut state = random_sampler(11);
+        let samples = 100_000;
+        let mut total = 0.0;
+        for _ in 0..samples {
+            let sample = sample_light(Vec3::ZERO, &params, &scene, &lights, &mut state);
+            assert!(sample.pdf > 0.0);
+            total += 1.0 / sample.pdf;
+        }
+        let expected = solid_angle(corners[0], corners[1], corners[2]);
+        let estimate = total / samples as f32;
+        assert!(
+            (estimate - expected).abs() < 0.01 * expected,
+            "{estimate} != {expected}"
+        );
+    }
+
+    #[test]
+    fn sphere_light_pdf_is_uniform_over_its_cone() {
+        let scene = Scene {
+            spheres: vec![Sphere {
+                position: Vec3::new(0.0, 0.0, -4.0),
+                radius: 1.0,
+                material: emissive(),
+            }],
+            ..Default::default()
+        };
+        let (params, lights) = light_params(&scene);
+        let mut state = random_sampler(5);
+        let cone = 2.0 * std::f32::consts::PI * (1.0 - (15.0f32 / 16.0).sqrt());
+        for _ in 0..100 {
+            let sample = sample_light(Vec3::ZERO, &params, &scene, &lights, &mut state);
+            assert!((sample.pdf * cone - 1.0).abs() < 1e-3);
+            assert!(sample.distance >= 3.0 && sample.distance <= 4.0);
+        }
+    }
+
+    #[test]
+    fn light_pdf_matches_the_sampled_pdf() {
+        let mut dim = emissive();
+        dim.emission_strength = 0.25;
+        let scene = Scene {
+            spheres: vec![
+                Sphere {
+                    position: Vec3::new(-2.0, 3.0, 0.0),
+                    radius: 0.5,
+                    material: emissive(),
+                },
+                Sphere {
+                    position: Vec3::new(0.0, -3.0, 0.0),
+                    radius: 1.0,
+                    material: Material::default(),
+                },
+            ],
+            triangles: vec![
+                TriangleMesh::new(
+                    vec![
+                        Vec3::new(1.0, 2.0, -1.0),
+                        Vec3::new(2.0, 2.0, 1.0),
+                        Vec3::new(0.0, 3.0, 1.0),
+                    ],
+                    dim,
+                ),
+                TriangleMesh::new(
+                    vec![
+                        Vec3::new(-1.0, -1.0, -3.0),
+                        Vec3::new(1.0, -1.0, -3.0),
+                        Vec3::new(0.0, 1.0, -3.0),
+                    ],
+                    emissive(),
+                ),
+            ],
+            ..Default::default()
+        };
+        let (params, lights) = light_params(&scene);
+        let tlas = Tlas::new(&scene.spheres, &scene.triangles);
+
+        // finding a light by tracing towards it gives the density it was sampled with
+        let mut state = random_sampler(17);
+        for _ in 0..1000 {
+            let sample = sample_light(Vec3::ZERO, &params, &scene, &lights, &mut state);
+            assert!(sample.pdf > 0.0);
+            let direction = sample.direction;
+            let hit = calculate_collision(ray(Vec3::ZERO, direction), &scene, &tlas);
+            assert!(hit.hit);
+            assert!((hit.distance - sample.distance).abs() < 1e-3);
+            let pdf = light_pdf(Vec3::ZERO, direction, &hit, &params, &scene);
+            assert!(
+                (pdf - sample.pdf).abs() < 1e-3 * sample.pdf,
+                "{pdf} != {}",
+                sample.pdf
+            );
+        }
+    }
+
+    #[test]
+    fn mis_weights_sum_to_one() {
+        for (a, b) in [(1.0, 1.0), (0.3, 2.0), (1e-3, 50.0), (7.0, 0.0)] {
+            let sum = mis_weight(a, b) + mis_weight(b, a);
+            assert!((sum - 1.0).abs() < 1e-6, "{a} {b}");
+        }
+        // a strategy that can't produce the sample leaves it all to the other
+        assert_eq!(mis_weight(2.0, 0.0), 1.0);
+        assert!(mis_weight(1.0, 3.0) < mis_weight(3.0, 1.0));
+    }
+
+    #[test]
+    fn analytic_lights_fall_off_with_the_squared_distance() {
+        let point = AnalyticLight {
+            position: Vec3::new(0.0, 5.0, 0.0),
+            kind: LightKind::Point as u32,
+            direction: Vec3::NEG_Y,
+            intensity: Vec3::splat(8.0),
+            cos_inner: 1.0,
+            cos_outer: 1.0,
+            profile: NO_PROFILE,
+            _pad: [0; 3],
+        };
+        let near = sample_analytic_light(Vec3::new(0.0, 3.0, 0.0), &point, &[], 0.5, 0.3, 0.7);
+        let far = sample_analytic_light(Vec3::new(0.0, 1.0, 0.0), &point, &[], 0.5, 0.3, 0.7);
+        assert!(near.emitted.abs_diff_eq(Vec3::splat(2.0), 1e-5));
+        assert!(far.emitted.abs_diff_eq(Vec3::splat(0.5), 1e-5));
+        assert!(near.direction.abs_diff_eq(Vec3::Y, 1e-5));
+        assert_eq!((near.distance, far.distance), (2.0, 4.0));
+        assert_eq!(near.pdf, 0.5);
+
+        // directional lights are as bright anywhere
+        let directional = AnalyticLight {
+            kind: LightKind::Directional as u32,
+            ..point
+        };
+        let near =
+            sample_analytic_light(Vec3::new(0.0, 3.0, 0.0), &directional, &[], 0.5, 0.3, 0.7);
+        let far =
+            sample_analytic_light(Vec3::new(9.0, -40.0, 2.0), &directional, &[], 0.5, 0.3, 0.7);
+        assert_eq!(near.emitted, far.emitted);
+        assert!(near.direction.abs_diff_eq(Vec3::Y, 1e-5));
+    }
+
+    #[test]
+    fn sobol_pairs_are_stratified() {
+        let pairs = (0..16)
+            .map(|index| {
+                let mut state = Sampler {
+                    kind: SamplerKind::Sobol as u32,
+                    seed: hash_u32(1234),
+                    pixel: UVec2::new(3, 5),
+                    index,
+                    dimension: 0,
+                };
+                random_pair(&mut state)
+            })
+            .collect::<Vec<_>>();
+        // scrambling keeps every 1/16 column and every cell of a 4x4 grid hit exactly once
+        let columns = pairs
+            .iter()
+            .map(|p| (p.x * 16.0) as u32)
+            .collect::<std::collections::HashSet<_>>();
+        let cells = pairs
+            .iter()
+            .map(|p| ((p.x * 4.0) as u32, (p.y * 4.0) as u32))
+            .collect::<std::collections::HashSet<_>>();
+        assert_eq!((columns.len(), cells.len()), (16, 16));
+    }
+}
@@ -0,0 +1,122 @@
+use std::{f32::consts::PI, path::Path};
+
+use glam::Vec3;
+
+use crate::color;
+
+/// Environment maps wider than this are scaled down, to fit in a GPU texture.
+pub const MAX_WIDTH: u32 = 8192;
+
+/// An equirectangular HDR image lighting the scene in place of the sky and sun,
+/// mapped like spheres are, with the tables to pick its texels by brightness.
+pub struct Environment {
+    pub width: u32,
+    pub height: u32,
+    /// Linear radiance, row-major from the top (+Y) row.
+    pub pixels: Vec<Vec3>,
+    /// For every row, the running sum of each texel's share of the row's weight.
+    pub conditional_cdf: Vec<f32>,
+    /// The running sum of each row's share of the whole map's weight.
+    pub marginal_cdf: Vec<f32>,
+}
+
+impl Environment {
+    /// Loads a Radiance `.hdr` file, or any other format the `image` crate reads.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, image::ImageError> {
+        let mut image = image::open(path)?.into_rgb32f();
+        if image.width() > MAX_WIDTH {
+            let height = (image.height() as u64 * MAX_WIDTH as u64 / image.width() as u64) as u32;
+            image = image::imageops::resize(
+                &image,
+                MAX_WIDTH,
+                height.max(1),
+                image::imageops::FilterType::Triangle,
+            );
+        }
+        let pixels = image.pixels().map(|p| Vec3::from_array(p.0)).collect();
+        Ok(Self::new(image.width(), image.height(), pixels))
+    }
+
+    /// Texels are weighted by luminance and by the solid angle they cover, which shrinks
+    /// towards the poles. Rows without any weight are picked from uniformly.
+    pub fn new(width: u32, height: u32, pixels: Vec<Vec3>) -> Self {
+        let mut conditional_cdf = Vec::with_capacity(pixels.len());
+        let mut row_weights = Vec::with_capacity(height as usize);
+        for (y, row) in pixels.chunks(width as usize).enumerate() {
+            let sin_theta = (PI * (y as f32 + 0.5) / height as f32).sin();
+            let weights = row
+                .iter()
+                .map(|p| p.dot(color::LUMINANCE).max(0.0) * sin_theta)
+                .collect::<Vec<_>>();
+            let total = weights.iter().sum::<f32>();
+            row_weights.push(total);
+            conditional_cdf.extend(running_shares(&weights, total));
+        }
+        let total = row_weights.iter().sum::<f32>();
+        Self {
+            width,
+            height,
+            pixels,
+            conditional_cdf,
+            marginal_cdf: running_shares(&row_weights, total),
+        }
+    }
+}
+
+/// Cumulative shares of `total`, ending at exactly 1 so searches always land in range.
+fn running_shares(weights: &[f32], total: f32) -> Vec<f32> {
+    let mut sum = 0.0;
+    let mut cdf = weights
+        .iter()
+        .enumerate()
+        .map(|(i, &weight)| {
+            sum += weight;
+            if total > 0.0 {
+                sum / total
+            } else {
+                (i + 1) as f32 / weights.len() as f32
+            }
+        })
+        .collect::<Vec<_>>();
+    if let Some(last) = cdf.last_mut() {
+        *last = 1.0;
+    }
+    cdf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shares_accumulate_to_one() {
+        let cdf = running_shares(&[1.0, 3.0, 0.0, 4.0], 8.0);
+        assert_eq!(cdf, [0.125, 0.5, 0.5, 1.0]);
+    }
+
+    #[test]
+    fn weightless_rows_are_uniform() {
+        let cdf = running_shares(&[0.0; 4], 0.0);
+        assert_eq!(cdf, [0.25, 0.5, 0.75, 1.0]);
+    }
+
+    #[test]
+    fn last_share_is_exactly_one_despite_rounding() {
+        let weights = [0.1; 10];
+        let cdf = running_shares(&weights, weights.iter().sum());
+        assert_eq!(*cdf.last().unwrap(), 1.0);
+        assert!(cdf.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[test]
+    fn bright_texel_dominates_its_row_and_the_map() {
+        let mut pixels = vec![Vec3::splat(0.1); 8 * 4];
+        pixels[8 + 5] = Vec3::splat(100.0);
+        let map = Environment::new(8, 4, pixels);
+        let row = &map.conditional_cdf[8..16];
+        assert!(row[5] - row[4] > 0.9);
+        assert!(map.marginal_cdf[1] - map.marginal_cdf[0] > 0.9);
+        assert_eq!(map.conditional_cdf.len(), 32);
+        assert_eq!(map.marginal_cdf.len(), 4);
+    }
+}
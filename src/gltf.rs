@@ -0,0 +1,355 @@
+use std::{collections::HashMap, path::Path};
+
+use ::gltf::{image::Format, mesh::Mode};
+use glam::{Mat4, Quat, Vec2, Vec3};
+use image::{DynamicImage, ImageBuffer, Pixel, RgbaImage};
+
+use crate::{texture::TextureSet, utils::*};
+
+pub struct GltfScene {
+    pub meshes: Vec<TriangleMesh>,
+    /// Position and rotation of the first camera found in the node hierarchy.
+    pub camera: Option<(Vec3, Quat)>,
+}
+
+/// Loads the default scene of a glTF/GLB file (or its first scene), flattening the node
+/// hierarchy so every triangle primitive becomes a [`TriangleMesh`] in world space,
+/// pre-multiplied by `transform`. Primitives without normals or tangents get generated
+/// ones.
+/// Images used by the materials are added to `textures`.
+pub fn load_gltf(
+    path: impl AsRef<Path>,
+    transform: Mat4,
+    textures: &mut TextureSet,
+) -> Result<GltfScene, ::gltf::Error> {
+    let (document, buffers, images) = ::gltf::import(path)?;
+    let mut images = Images {
+        data: images.into_iter().map(Some).collect(),
+        indices: HashMap::new(),
+        textures,
+    };
+    let mut scene = GltfScene {
+        meshes: Vec::new(),
+        camera: None,
+    };
+    if let Some(root) = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+    {
+        for node in root.nodes() {
+            visit_node(&node, transform, &buffers, &mut images, &mut scene);
+        }
+    }
+    Ok(scene)
+}
+
+/// Converts the file's images into scene textures the first time a material uses them.
+struct Images<'a> {
+    data: Vec<Option<::gltf::image::Data>>,
+    indices: HashMap<usize, u32>,
+    textures: &'a mut TextureSet,
+}
+
+impl Images<'_> {
+    fn texture(&mut self, texture: Option<::gltf::texture::Texture>) -> u32 {
+        let Some(texture) = texture else {
+            return NO_TEXTURE;
+        };
+        let image = texture.source().index();
+        if let Some(&index) = self.indices.get(&image) {
+            return index;
+        }
+        let index = self.data[image]
+            .take()
+            .and_then(convert_image)
+            .map_or(NO_TEXTURE, |image| self.textures.push(image));
+        self.indices.insert(image, index);
+        index
+    }
+}
+
+fn convert_image(data: ::gltf::image::Data) -> Option<RgbaImage> {
+    let (w, h, pixels) = (data.width, data.height, &data.pixels);
+    let image = match data.format {
+        Format::R8 => DynamicImage::ImageLuma8(image_buffer(w, h, pixels)?),
+        Format::R8G8 => DynamicImage::ImageLumaA8(image_buffer(w, h, pixels)?),
+        Format::R8G8B8 => DynamicImage::ImageRgb8(image_buffer(w, h, pixels)?),
+        Format::R8G8B8A8 => DynamicImage::ImageRgba8(image_buffer(w, h, pixels)?),
+        Format::R16 => DynamicImage::ImageLuma16(image_buffer(w, h, pixels)?),
+        Format::R16G16 => DynamicImage::ImageLumaA16(image_buffer(w, h, pixels)?),
+        Format::R16G16B16 => DynamicImage::ImageRgb16(image_buffer(w, h, pixels)?),
+        Format::R16G16B16A16 => DynamicImage::ImageRgba16(image_buffer(w, h, pixels)?),
+        Format::R32G32B32FLOAT => DynamicImage::ImageRgb32F(image_buffer(w, h, pixels)?),
+        Format::R32G32B32A32FLOAT => DynamicImage::ImageRgba32F(image_buffer(w, h, pixels)?),
+    };
+    Some(image.to_rgba8())
+}
+
+/// Reinterprets the native-endian bytes gltf decodes images into.
+fn image_buffer<P: Pixel>(
+    width: u32,
+    height: u32,
+    bytes: &[u8],
+) -> Option<ImageBuffer<P, Vec<P::Subpixel>>>
+where
+    P::Subpixel: bytemuck::Pod,
+{
+    ImageBuffer::from_raw(width, height, bytemuck::pod_collect_to_vec(bytes))
+}
+
+fn visit_node(
+    node: &::gltf::Node,
+    parent: Mat4,
+    buffers: &[::gltf::buffer::Data],
+    images: &mut Images,
+    scene: &mut GltfScene,
+) {
+    let transform = parent * Mat4::from_cols_array_2d(&node.transform().matrix());
+
+    if node.camera().is_some() && scene.camera.is_none() {
+        let (_, rotation, translation) = transform.to_scale_rotation_translation();
+        scene.camera = Some((translation, rotation));
+    }
+
+    if let Some(mesh) = node.mesh() {
+        for primitive in mesh.primitives() {
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+            let Some(positions) = reader.read_positions() else {
+                continue;
+            };
+            let mut vertices = positions
+                .map(|p| {
+                    Vertex::new(
+                        transform.transform_point3(Vec3::from(p)),
+                        Vec3::ZERO,
+                        Vec2::ZERO,
+                    )
+                })
+                .collect::<Vec<_>>();
+            let uvs = reader.read_tex_coords(0);
+            let has_uvs = uvs.is_some();
+            if let Some(uvs) = uvs {
+                for (vertex, [u, v]) in vertices.iter_mut().zip(uvs.into_f32()) {
+                    vertex.u = u;
+                    vertex.v = v;
+                }
+            }
+            let indices = match reader.read_indices() {
+                Some(indices) => indices.into_u32().collect(),
+                None => (0..vertices.len() as u32).collect::<Vec<_>>(),
+            };
+            let Some(mut indices) = triangle_list(primitive.mode(), &indices) else {
+                continue;
+            };
+
+            // a mirroring transform flips the winding, which decides the front face
+            if transform.determinant() < 0.0 {
+                for tri in indices.chunks_exact_mut(3) {
+                    tri.swap(1, 2);
+                }
+            }
+            match reader.read_normals() {
+                Some(normals) => {
+                    let normal_matrix = transform.inverse().transpose();
+                    for (vertex, n) in vertices.iter_mut().zip(normals) {
+                        vertex.normal = normal_matrix
+                            .transform_vector3(Vec3::from(n))
+                            .normalize_or_zero();
+                    }
+                }
+                None => generate_normals(&mut vertices, &indices),
+            }
+            match reader.read_tangents() {
+                Some(tangents) => {
+                    // mirroring also flips which way the bitangent points
+                    let handedness = transform.determinant().signum();
+                    for (vertex, [x, y, z, w]) in vertices.iter_mut().zip(tangents) {
+                        let tangent = transform.transform_vector3(Vec3::new(x, y, z));
+                        vertex.tangent = tangent.normalize_or_zero().extend(w * handedness);
+                    }
+                }
+                None if has_uvs => generate_tangents(&mut vertices, &indices),
+                None => {}
+            }
+            if !indices.is_empty() {
+                scene.meshes.push(TriangleMesh::indexed(
+                    vertices,
+                    indices,
+                    convert_material(&primitive.material(), images),
+                ));
+            }
+        }
+    }
+
+    for child in node.children() {
+        visit_node(&child, transform, buffers, images, scene);
+    }
+}
+
+/// Expands strips and fans into a plain triangle list. Points and lines yield `None`.
+fn triangle_list(mode: Mode, indices: &[u32]) -> Option<Vec<u32>> {
+    let n = indices.len();
+    match mode {
+        Mode::Triangles => Some(indices[..n - n % 3].to_vec()),
+        Mode::TriangleStrip => Some(
+            (0..n.saturating_sub(2))
+                .flat_map(|i| {
+                    if i % 2 == 0 {
+                        [indices[i], indices[i + 1], indices[i + 2]]
+                    } else {
+                        [indices[i + 1], indices[i], indices[i + 2]]
+                    }
+                })
+                .collect(),
+        ),
+        Mode::TriangleFan => Some(
+            (1..n.saturating_sub(1))
+                .flat_map(|i| [indices[0], indices[i], indices[i + 1]])
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+/// Maps the metallic-roughness model onto [`Material`]: base color becomes the diffuse
+/// color, roughness and metalness carry over, and transmissive materials become
+/// dielectrics with their IOR and volume attenuation. `doubleSided` decides whether back
+/// faces are hit. The base color, metallic-roughness, emissive and normal textures are
+/// kept.
+fn convert_material(material: &::gltf::Material, images: &mut Images) -> Material {
+    let pbr = material.pbr_metallic_roughness();
+    let emission =
+        Vec3::from(material.emissive_factor()) * material.emissive_strength().unwrap_or(1.0);
+    let emission_strength = emission.max_element();
+    let glass = material
+        .transmission()
+        .is_some_and(|t| t.transmission_factor() > 0.0);
+    // the attenuation color is reached after the attenuation distance
+    let (absorption_color, absorption_density) = match material.volume() {
+        Some(volume) if glass && volume.attenuation_distance().is_finite() => (
+            Vec3::from(volume.attenuation_color()),
+            1.0 / volume.attenuation_distance(),
+        ),
+        _ => (Vec3::ONE, 0.0),
+    };
+
+    Material {
+        diffuse_color: Vec3::from_slice(&pbr.base_color_factor()[..3]),
+        roughness: pbr.roughness_factor(),
+        metallic: pbr.metallic_factor(),
+        absorption_color,
+        absorption_density,
+        emission_color: if emission_strength > 0.0 {
+            emission / emission_strength
+        } else {
+            Vec3::ZERO
+        },
+        emission_strength,
+        refractive_index: if glass {
+            material.ior().unwrap_or(DEFAULT_REFRACTIVE_INDEX)
+        } else {
+            1.0
+        },
+        kind: if glass {
+            MaterialKind::Dielectric
+        } else {
+            MaterialKind::Diffuse
+        } as u32,
+        flags: if material.double_sided() {
+            Material::TWO_SIDED
+        } else {
+            0
+        },
+        diffuse_texture: images.texture(pbr.base_color_texture().map(|info| info.texture())),
+        roughness_texture: images
+            .texture(pbr.metallic_roughness_texture().map(|info| info.texture())),
+        emission_texture: images.texture(material.emissive_texture().map(|info| info.texture())),
+        normal_texture: images.texture(material.normal_texture().map(|info| info.texture())),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::Vec4;
+
+    /// Writes a glTF file with one triangle facing +Z, `u` along +X and `v` down the image,
+    /// with or without tangents, and returns its path.
+    fn write_triangle(name: &str, with_tangents: bool) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("gltf-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let floats: [&[f32]; 4] = [
+            &[0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0],
+            &[0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0],
+            &[0.0, 1.0, 1.0, 1.0, 0.0, 0.0],
+            &[1.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0],
+        ];
+        let bytes = floats
+            .concat()
+            .iter()
+            .flat_map(|f| f.to_le_bytes())
+            .collect::<Vec<_>>();
+        std::fs::write(dir.join(format!("{name}.bin")), &bytes).unwrap();
+
+        let tangent = if with_tangents {
+            r#", "TANGENT": 3"#
+        } else {
+            ""
+        };
+        let json = format!(
+            r#"{{
+                "asset": {{"version": "2.0"}},
+                "scenes": [{{"nodes": [0]}}],
+                "nodes": [{{"mesh": 0}}],
+                "meshes": [{{"primitives": [{{"attributes": {{
+                    "POSITION": 0, "NORMAL": 1, "TEXCOORD_0": 2{tangent}
+                }}}}]}}],
+                "buffers": [{{"uri": "{name}.bin", "byteLength": {}}}],
+                "bufferViews": [
+                    {{"buffer": 0, "byteOffset": 0, "byteLength": 36}},
+                    {{"buffer": 0, "byteOffset": 36, "byteLength": 36}},
+                    {{"buffer": 0, "byteOffset": 72, "byteLength": 24}},
+                    {{"buffer": 0, "byteOffset": 96, "byteLength": 48}}
+                ],
+                "accessors": [
+                    {{"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                      "min": [0, 0, 0], "max": [1, 1, 0]}},
+                    {{"bufferView": 1, "componentType": 5126, "count": 3, "type": "VEC3"}},
+                    {{"bufferView": 2, "componentType": 5126, "count": 3, "type": "VEC2"}},
+                    {{"bufferView": 3, "componentType": 5126, "count": 3, "type": "VEC4"}}
+                ]
+            }}"#,
+            bytes.len()
+        );
+        let path = dir.join(format!("{name}.gltf"));
+        std::fs::write(&path, json).unwrap();
+        path
+    }
+
+    fn load_tangents(path: &Path, transform: Mat4) -> Vec<Vec4> {
+        let scene = load_gltf(path, transform, &mut TextureSet::default()).unwrap();
+        assert_eq!(scene.meshes.len(), 1);
+        scene.meshes[0].vertices.iter().map(|v| v.tangent).collect()
+    }
+
+    #[test]
+    fn file_tangents_match_generated_ones_when_mirrored() {
+        let given = write_triangle("given", true);
+        let generated = write_triangle("generated", false);
+        for transform in [Mat4::IDENTITY, Mat4::from_scale(Vec3::new(-1.0, 1.0, 1.0))] {
+            let expected = load_tangents(&generated, transform);
+            let tangents = load_tangents(&given, transform);
+            for (tangent, expected) in tangents.iter().zip(&expected) {
+                assert!(
+                    tangent.abs_diff_eq(*expected, 1e-5),
+                    "{tangent} != {expected}"
+                );
+            }
+            // u runs along the mirrored X axis, the bitangent still up the image
+            let x = transform.transform_vector3(Vec3::X);
+            assert!(tangents[0].truncate().abs_diff_eq(x, 1e-5));
+            assert_eq!(tangents[0].w, x.x);
+        }
+    }
+}
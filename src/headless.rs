@@ -0,0 +1,97 @@
+use std::path::{Path, PathBuf};
+
+use glam::{Mat3A, Vec3};
+
+use raytracing_compute::{Renderer, Scene, cpu, request_device, utils::*};
+
+pub struct HeadlessOptions {
+    pub width: u32,
+    pub height: u32,
+    pub frames: u32,
+    pub output: PathBuf,
+    pub cpu: bool,
+}
+
+impl Default for HeadlessOptions {
+    fn default() -> Self {
+        Self {
+            width: 1280,
+            height: 720,
+            frames: 100,
+            output: PathBuf::from("render.png"),
+            cpu: false,
+        }
+    }
+}
+
+pub async fn run(options: HeadlessOptions, scene: &Scene) {
+    let instance = wgpu::Instance::new(&Default::default());
+    let adapter = instance
+        .request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: Default::default(),
+            force_fallback_adapter: false,
+            compatible_surface: None,
+        })
+        .await
+        .expect("error finding adapter");
+    let (device, queue) = request_device(&adapter).await;
+
+    let HeadlessOptions {
+        width,
+        height,
+        frames,
+        output,
+        ..
+    } = options;
+
+    let mut renderer = Renderer::new(&device, &queue, width, height);
+    renderer.set_scene(scene);
+    renderer.set_camera(scene.camera);
+    for _ in 0..frames {
+        renderer.render_frame();
+    }
+
+    save(&output, width, height, renderer.read_back());
+    println!("wrote {} frames to {}", frames, output.display());
+}
+
+/// Renders the scene with the CPU reference tracer instead of the compute shader.
+pub fn run_cpu(options: HeadlessOptions, scene: &Scene) {
+    let HeadlessOptions {
+        width,
+        height,
+        frames,
+        output,
+        ..
+    } = options;
+
+    let camera = scene.camera;
+    let params = IParams {
+        camera_pos: camera.position,
+        camera_dir: Mat3A::from_quat(camera.rotation),
+        light_dir: camera.light_dir,
+        exposure: camera.exposure,
+        width,
+        height,
+        sky: scene.sky,
+        spectral: scene.spectral as u32,
+        sampler_kind: scene.sampler as u32,
+        ..Default::default()
+    };
+    let pixels = cpu::render(&params, scene, frames)
+        .into_iter()
+        .flat_map(|c| {
+            let [r, g, b] = (c.clamp(Vec3::ZERO, Vec3::ONE) * 255.0).round().to_array();
+            [r as u8, g as u8, b as u8, 255]
+        })
+        .collect();
+    save(&output, width, height, pixels);
+    println!("wrote {} cpu frames to {}", frames, output.display());
+}
+
+fn save(output: &Path, width: u32, height: u32, pixels: Vec<u8>) {
+    image::RgbaImage::from_raw(width, height, pixels)
+        .expect("pixel buffer size mismatch")
+        .save(output)
+        .expect("error writing image");
+}
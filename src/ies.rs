@@ -0,0 +1,280 @@
+use std::{fmt, path::Path};
+
+/// Samples of each profile from straight along the light's direction (0°) to straight
+/// against it (180°), both included.
+pub const VERTICAL_SAMPLES: u32 = 128;
+/// Samples of each profile around the light's direction, from 0° up to but excluding 360°.
+pub const HORIZONTAL_SAMPLES: u32 = 32;
+
+/// A fixture's measured distribution of intensity, from an IESNA LM-63 `.ies` file,
+/// resampled onto a regular grid of angles and scaled so its brightest direction is 1.
+pub struct IesProfile {
+    /// One row of [`VERTICAL_SAMPLES`] per horizontal angle.
+    pub intensity: Vec<f32>,
+    /// Average of the intensity over the whole sphere, for the light's share of power.
+    pub mean: f32,
+}
+
+#[derive(Debug)]
+pub enum IesError {
+    Io(std::io::Error),
+    /// The file ended early or had something else where a number was expected.
+    Malformed,
+    /// Type A or B photometry, which is measured about a different axis than type C.
+    UnsupportedPhotometry(u32),
+}
+
+impl fmt::Display for IesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IesError::Io(err) => write!(f, "{err}"),
+            IesError::Malformed => write!(f, "malformed IES data"),
+            IesError::UnsupportedPhotometry(kind) => {
+                write!(f, "unsupported photometric type {kind}, only type C is")
+            }
+        }
+    }
+}
+
+impl std::error::Error for IesError {}
+
+impl IesProfile {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, IesError> {
+        let bytes = std::fs::read(path).map_err(IesError::Io)?;
+        // header keywords may hold text in any encoding
+        Self::parse(&String::from_utf8_lossy(&bytes))
+    }
+
+    /// Reads type C photometry, where vertical angles are measured from straight down the
+    /// fixture's axis and horizontal ones around it. Profiles covering only a half or a
+    /// quarter of the horizontal angles are mirrored to fill the rest.
+    pub fn parse(source: &str) -> Result<Self, IesError> {
+        // the keyword header ends at the TILT line, the numbers follow
+        let mut lines = source.lines();
+        let tilt = lines
+            .find_map(|line| line.trim().strip_prefix("TILT="))
+            .ok_or(IesError::Malformed)?
+            .trim()
+            .to_string();
+        let mut numbers = lines
+            .flat_map(|line| line.split(|c: char| c.is_whitespace() || c == ','))
+            .filter(|token| !token.is_empty())
+            .map(|token| token.parse::<f32>().map_err(|_| IesError::Malformed));
+        let mut next = move || numbers.next().unwrap_or(Err(IesError::Malformed));
+
+        if tilt == "INCLUDE" {
+            // lamp-to-luminaire geometry, then the tilt angles and their factors, which
+            // only matter for lamps mounted at an angle
+            next()?;
+            let count = next()? as usize;
+            for _ in 0..count * 2 {
+                next()?;
+            }
+        }
+
+        let _lamp_count = next()?;
+        let _lumens_per_lamp = next()?;
+        let _multiplier = next()?;
+        let vertical_count = next()? as usize;
+        let horizontal_count = next()? as usize;
+        let photometric_type = next()? as u32;
+        if photometric_type != 1 {
+            return Err(IesError::UnsupportedPhotometry(photometric_type));
+        }
+        // units and the luminous opening's width, length and height, then the ballast
+        // factor, a reserved field and the input watts
+        for _ in 0..7 {
+            next()?;
+        }
+        if vertical_count == 0 || horizontal_count == 0 {
+            return Err(IesError::Malformed);
+        }
+        let vertical_angles = (0..vertical_count)
+            .map(|_| next())
+            .collect::<Result<Vec<_>, _>>()?;
+        let horizontal_angles = (0..horizontal_count)
+            .map(|_| next())
+            .collect::<Result<Vec<_>, _>>()?;
+        let candela = (0..vertical_count * horizontal_count)
+            .map(|_| next())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let lookup = |vertical: f32, horizontal: f32| {
+            let horizontal = mirror_horizontal(horizontal, &horizontal_angles);
+            let (h, ht) = interpolate(&horizontal_angles, horizontal);
+            let slice = |h: usize| {
+                let row = &candela[h * vertical_count..(h + 1) * vertical_count];
+                // nothing is emitted outside the measured vertical angles
+                if vertical < vertical_angles[0] || vertical > vertical_angles[vertical_count - 1] {
+                    return 0.0;
+                }
+                let (v, vt) = interpolate(&vertical_angles, vertical);
+                let above = row[(v + 1).min(vertical_count - 1)];
+                row[v] + (above - row[v]) * vt
+            };
+            let next_slice = (h + 1).min(horizontal_count - 1);
+            slice(h) + (slice(next_slice) - slice(h)) * ht
+        };
+
+        let mut intensity = Vec::with_capacity((VERTICAL_SAMPLES * HORIZONTAL_SAMPLES) as usize);
+        for y in 0..HORIZONTAL_SAMPLES {
+            let horizontal = y as f32 * 360.0 / HORIZONTAL_SAMPLES as f32;
+            for x in 0..VERTICAL_SAMPLES {
+                let vertical = x as f32 * 180.0 / (VERTICAL_SAMPLES - 1) as f32;
+                intensity.push(lookup(vertical, horizontal).max(0.0));
+            }
+        }
+        let peak = intensity.iter().copied().fold(0.0, f32::max);
+        if peak > 0.0 {
+            intensity.iter_mut().for_each(|value| *value /= peak);
+        }
+
+        // each sample stands for a band of solid angle proportional to sin(vertical)
+        let (mut sum, mut weight) = (0.0, 0.0);
+        for row in intensity.chunks(VERTICAL_SAMPLES as usize) {
+            for (x, value) in row.iter().enumerate() {
+                let vertical = x as f32 * std::f32::consts::PI / (VERTICAL_SAMPLES - 1) as f32;
+                sum += value * vertical.sin();
+                weight += vertical.sin();
+            }
+        }
+        Ok(Self {
+            intensity,
+            mean: sum / weight,
+        })
+    }
+}
+
+/// Folds a horizontal angle in degrees into the range the profile covers: a single angle
+/// means it is the same all around, up to 90° means the quadrants mirror each other, and
+/// up to 180° means the two halves do.
+fn mirror_horizontal(angle: f32, angles: &[f32]) -> f32 {
+    let last = angles[angles.len() - 1];
+    if angles.len() == 1 {
+        angles[0]
+    } else if last <= 90.0 {
+        let angle = angle % 180.0;
+        if angle > 90.0 { 180.0 - angle } else { angle }
+    } else if last <= 180.0 && angle > 180.0 {
+        360.0 - angle
+    } else {
+        angle
+    }
+}
+
+/// Index of the last of the ascending `angles` at or below `angle`, and how far `angle`
+/// is towards the next one, clamped to the ends.
+fn interpolate(angles: &[f32], angle: f32) -> (usize, f32) {
+    let upper = angles.partition_point(|&a| a <= angle);
+    if upper == 0 {
+        return (0, 0.0);
+    }
+    if upper == angles.len() {
+        return (angles.len() - 1, 0.0);
+    }
+    let (a, b) = (angles[upper - 1], angles[upper]);
+    (upper - 1, (angle - a) / (b - a))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A type C file with the given angles and candela values after a minimal header.
+    fn file(tilt: &str, vertical: &[f32], horizontal: &[f32], candela: &[f32]) -> String {
+        let join = |values: &[f32]| {
+            values
+                .iter()
+                .map(f32::to_string)
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        format!(
+            "IESNA:LM-63-2002\n[TEST] fixture\nTILT={tilt}\n1 1000 1 {} {} 1 2 0 0 0\n1 1 100\n{}\n{}\n{}\n",
+            vertical.len(),
+            horizontal.len(),
+            join(vertical),
+            join(horizontal),
+            join(candela),
+        )
+    }
+
+    fn sample(profile: &IesProfile, vertical: u32, horizontal: u32) -> f32 {
+        profile.intensity[(horizontal * VERTICAL_SAMPLES + vertical) as usize]
+    }
+
+    #[test]
+    fn uniform_profile_is_one_everywhere() {
+        let source = file("NONE", &[0.0, 90.0, 180.0], &[0.0], &[250.0; 3]);
+        let profile = IesProfile::parse(&source).unwrap();
+        assert_eq!(
+            profile.intensity.len(),
+            (VERTICAL_SAMPLES * HORIZONTAL_SAMPLES) as usize
+        );
+        assert!(profile.intensity.iter().all(|&value| value == 1.0));
+        assert!((profile.mean - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn downlight_is_dark_above_and_peaks_at_one() {
+        let source = file("NONE", &[0.0, 45.0, 90.0], &[0.0], &[800.0, 400.0, 0.0]);
+        let profile = IesProfile::parse(&source).unwrap();
+        assert_eq!(sample(&profile, 0, 0), 1.0);
+        assert_eq!(sample(&profile, VERTICAL_SAMPLES - 1, 0), 0.0);
+        assert!(profile.mean > 0.0 && profile.mean < 0.5);
+    }
+
+    #[test]
+    fn quadrant_symmetric_profile_is_mirrored() {
+        // brighter towards 0° than 90°, measured over one quadrant only
+        let source = file(
+            "NONE",
+            &[0.0, 180.0],
+            &[0.0, 90.0],
+            &[100.0, 100.0, 50.0, 50.0],
+        );
+        let profile = IesProfile::parse(&source).unwrap();
+        let quarter = HORIZONTAL_SAMPLES / 4;
+        assert_eq!(sample(&profile, 0, 0), 1.0);
+        assert_eq!(sample(&profile, 0, quarter), 0.5);
+        assert_eq!(sample(&profile, 0, 2 * quarter), 1.0);
+        assert_eq!(sample(&profile, 0, 3 * quarter), 0.5);
+        assert_eq!(sample(&profile, 0, quarter / 2), 0.75);
+    }
+
+    #[test]
+    fn included_tilt_data_is_skipped() {
+        let source = file(
+            "INCLUDE\n1\n2\n0 90\n1 1",
+            &[0.0, 180.0],
+            &[0.0],
+            &[10.0; 2],
+        );
+        let profile = IesProfile::parse(&source).unwrap();
+        assert!((profile.mean - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn type_a_photometry_is_rejected() {
+        let source =
+            file("NONE", &[0.0, 180.0], &[0.0], &[10.0; 2]).replacen(" 1 2 0", " 3 2 0", 1);
+        assert!(matches!(
+            IesProfile::parse(&source),
+            Err(IesError::UnsupportedPhotometry(3))
+        ));
+    }
+
+    #[test]
+    fn truncated_or_headerless_files_are_malformed() {
+        let source = file("NONE", &[0.0, 90.0, 180.0], &[0.0], &[10.0; 3]);
+        let truncated = source.trim_end().trim_end_matches("10").trim_end();
+        assert!(matches!(
+            IesProfile::parse(truncated),
+            Err(IesError::Malformed)
+        ));
+        assert!(matches!(
+            IesProfile::parse("1 1000 1 2 1 1"),
+            Err(IesError::Malformed)
+        ));
+    }
+}
@@ -0,0 +1,18 @@
+pub mod blue_noise;
+pub mod bvh;
+pub mod color;
+mod compute;
+pub mod cpu;
+pub mod environment;
+pub mod gltf;
+pub mod ies;
+pub mod lights;
+pub mod obj;
+mod renderer;
+pub mod scene;
+pub mod texture;
+pub mod utils;
+
+pub use compute::OUTPUT_FORMAT;
+pub use renderer::{Camera, REQUIRED_FEATURES, Renderer, request_device};
+pub use scene::{Scene, SceneError};
@@ -0,0 +1,286 @@
+use std::f32::consts::PI;
+
+use glam::Vec3;
+
+use crate::{color, ies::IesProfile, utils::*};
+
+/// Lists every emissive sphere and triangle and every analytic light, to be picked in
+/// proportion to the power it emits, along with their total power. Objects are numbered
+/// like [`crate::bvh::Tlas::objects`].
+pub fn build(
+    spheres: &[Sphere],
+    meshes: &[TriangleMesh],
+    analytic: &[AnalyticLight],
+    profiles: &[IesProfile],
+) -> (Vec<Light>, f32) {
+    let sphere_lights = spheres.iter().enumerate().map(|(i, sphere)| {
+        let area = 4.0 * PI * sphere.radius * sphere.radius;
+        (i as u32, 0, power(&sphere.material) * area)
+    });
+    let triangle_lights = meshes
+        .iter()
+        .enumerate()
+        .filter(|(_, mesh)| power(&mesh.material) > 0.0)
+        .flat_map(|(i, mesh)| {
+            let object = (spheres.len() + i) as u32;
+            (0..mesh.indices.len() / 3).map(move |triangle| {
+                let [v0, v1, v2] = mesh.triangle(triangle).map(|v| v.position);
+                let area = 0.5 * (v1 - v0).cross(v2 - v0).length();
+                (object, triangle as u32, power(&mesh.material) * area)
+            })
+        });
+    let scene_radius = scene_radius(spheres, meshes);
+    let analytic_lights = analytic.iter().enumerate().map(|(i, light)| {
+        (
+            ANALYTIC_LIGHT,
+            i as u32,
+            analytic_power(light, scene_radius, profiles),
+        )
+    });
+    let candidates = sphere_lights
+        .chain(triangle_lights)
+        .chain(analytic_lights)
+        .filter(|&(_, _, power)| power > 0.0)
+        .collect::<Vec<_>>();
+
+    let total = candidates.iter().map(|&(_, _, power)| power).sum::<f32>();
+    let mut cdf = 0.0;
+    let mut lights = candidates
+        .into_iter()
+        .map(|(object, triangle, power)| {
+            let probability = power / total;
+            cdf += probability;
+            Light {
+                object,
+                triangle,
+                probability,
+                cdf,
+            }
+        })
+        .collect::<Vec<_>>();
+    // so the search always lands on a light, whatever the rounding
+    if let Some(last) = lights.last_mut() {
+        last.cdf = 1.0;
+    }
+    (lights, total)
+}
+
+/// Power emitted per unit area, by luminance. A light's share of the total is this times
+/// its area.
+pub fn power(material: &Material) -> f32 {
+    material.emission_strength * material.emission_color.dot(color::LUMINANCE)
+}
+
+/// Power of an analytic light, by luminance. Directional lights count what falls on a disk
+/// as wide as the scene, and profiles scale the rest by their average.
+pub fn analytic_power(light: &AnalyticLight, scene_radius: f32, profiles: &[IesProfile]) -> f32 {
+    let mut intensity = light.intensity.dot(color::LUMINANCE);
+    if let Some(profile) = profiles.get(light.profile as usize) {
+        intensity *= profile.mean;
+    }
+    match LightKind::from_u32(light.kind) {
+        Some(LightKind::Point) => 4.0 * PI * intensity,
+        // roughly the solid angle of the cone, counting the fading part half
+        Some(LightKind::Spot) => {
+            2.0 * PI * (1.0 - 0.5 * (light.cos_inner + light.cos_outer)) * intensity
+        }
+        Some(LightKind::Directional) => PI * scene_radius * scene_radius * intensity,
+        None => 0.0,
+    }
+}
+
+/// Radius of a sphere around all the geometry.
+fn scene_radius(spheres: &[Sphere], meshes: &[TriangleMesh]) -> f32 {
+    let points = spheres
+        .iter()
+        .flat_map(|sphere| {
+            [
+                sphere.position - Vec3::splat(sphere.radius),
+                sphere.position + Vec3::splat(sphere.radius),
+            ]
+        })
+        .chain(
+            meshes
+                .iter()
+                .flat_map(|mesh| mesh.vertices.iter().map(|v| v.position)),
+        )
+        .collect::<Vec<_>>();
+    if points.is_empty() {
+        return 0.0;
+    }
+    let bounds = Aabb::from_points(&points);
+    0.5 * (bounds.max - bounds.min).truncate().length()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn emissive(color: Vec3, strength: f32) -> Material {
+        Material {
+            emission_color: color,
+            emission_strength: strength,
+            kind: MaterialKind::Emissive as u32,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn lights_are_picked_by_power() {
+        let spheres = [
+            Sphere {
+                position: Vec3::ZERO,
+                radius: 1.0,
+                material: emissive(Vec3::ONE, 2.0),
+            },
+            Sphere {
+                position: Vec3::X * 3.0,
+                radius: 1.0,
+                material: Material::default(),
+            },
+        ];
+        // two triangles of area 0.5 and 2
+        let meshes = [TriangleMesh::new(
+            vec![
+                Vec3::ZERO,
+                Vec3::X,
+                Vec3::Y,
+                Vec3::ZERO,
+                Vec3::X * 2.0,
+                Vec3::Y * 2.0,
+            ],
+            emissive(Vec3::X, 5.0),
+        )];
+        let (lights, total_power) = build(&spheres, &meshes, &[], &[]);
+
+        // the dark sphere is left out
+        assert_eq!(
+            lights
+                .iter()
+                .map(|light| (light.object, light.triangle))
+                .collect::<Vec<_>>(),
+            [(0, 0), (2, 0), (2, 1)]
+        );
+        let powers = [
+            power(&spheres[0].material) * 4.0 * PI,
+            power(&meshes[0].material) * 0.5,
+            power(&meshes[0].material) * 2.0,
+        ];
+        let total = powers.iter().sum::<f32>();
+        assert!((total_power - total).abs() < 1e-4 * total);
+        let mut cdf = 0.0;
+        for (light, power) in lights.iter().zip(powers) {
+            assert!((light.probability - power / total).abs() < 1e-6);
+            cdf += light.probability;
+            assert!((light.cdf - cdf).abs() < 1e-6);
+        }
+        assert_eq!(lights.last().unwrap().cdf, 1.0);
+    }
+
+    #[test]
+    fn nothing_emissive_gives_no_lights() {
+        let spheres = [Sphere {
+            position: Vec3::ZERO,
+            radius: 1.0,
+            material: Material::default(),
+        }];
+        let (lights, total_power) = build(&spheres, &[], &[], &[]);
+        assert!(lights.is_empty());
+        assert_eq!(total_power, 0.0);
+    }
+
+    /// Integrates the intensity `light` sends out over all directions, by luminance.
+    fn emitted_power(light: &AnalyticLight, profiles: &[IesProfile]) -> f32 {
+        let (rings, segments) = (2000, 64);
+        let axis = light.direction.normalize();
+        let (side, up) = (
+            axis.any_orthonormal_vector(),
+            axis.cross(axis.any_orthonormal_vector()),
+        );
+        let mut total = 0.0;
+        for i in 0..rings {
+            let cos = 1.0 - 2.0 * (i as f32 + 0.5) / rings as f32;
+            let sin = (1.0 - cos * cos).sqrt();
+            for j in 0..segments {
+                let phi = 2.0 * PI * (j as f32 + 0.5) / segments as f32;
+                let offset = axis * cos + (side * phi.cos() + up * phi.sin()) * sin;
+                let sample = crate::cpu::sample_analytic_light(
+                    light.position + offset,
+                    light,
+                    profiles,
+                    1.0,
+                    0.0,
+                    0.0,
+                );
+                // unit distance, so no falloff to undo
+                total += sample.emitted.dot(color::LUMINANCE);
+            }
+        }
+        total * 4.0 * PI / (rings * segments) as f32
+    }
+
+    #[test]
+    fn analytic_power_matches_the_emitted_intensity() {
+        let point = AnalyticLight {
+            position: Vec3::new(1.0, 2.0, 3.0),
+            kind: LightKind::Point as u32,
+            direction: Vec3::NEG_Y,
+            intensity: Vec3::new(3.0, 2.0, 1.0),
+            profile: NO_PROFILE,
+            ..Default::default()
+        };
+        let spot = AnalyticLight {
+            kind: LightKind::Spot as u32,
+            cos_inner: 20.0f32.to_radians().cos(),
+            cos_outer: 35.0f32.to_radians().cos(),
+            ..point
+        };
+        for light in [point, spot] {
+            let expected = emitted_power(&light, &[]);
+            let power = analytic_power(&light, 0.0, &[]);
+            assert!(
+                (power - expected).abs() < 1e-3 * expected,
+                "{power} != {expected}"
+            );
+        }
+
+        // a profile scales the power by its mean, its samples being spread evenly
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("assets/ies");
+        let profiles = [
+            IesProfile::load(dir.join("downlight.ies")).unwrap(),
+            IesProfile::load(dir.join("wallwasher.ies")).unwrap(),
+        ];
+        for profile in 0..profiles.len() as u32 {
+            let light = AnalyticLight { profile, ..point };
+            let expected = emitted_power(&light, &profiles);
+            let power = analytic_power(&light, 0.0, &profiles);
+            assert!(
+                (power - expected).abs() < 0.02 * expected,
+                "{power} != {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn directional_power_covers_the_scene() {
+        let light = AnalyticLight {
+            kind: LightKind::Directional as u32,
+            direction: Vec3::NEG_Y,
+            intensity: Vec3::ONE,
+            cos_outer: 1.0,
+            profile: NO_PROFILE,
+            ..Default::default()
+        };
+        let spheres = [Sphere {
+            position: Vec3::new(5.0, 0.0, 0.0),
+            radius: 2.0,
+            material: Material::default(),
+        }];
+        // the box around the sphere is 4 wide, so the disk has half its diagonal as radius
+        assert!((scene_radius(&spheres, &[]) - 12f32.sqrt()).abs() < 1e-5);
+        assert!((analytic_power(&light, 2.0, &[]) - 4.0 * PI).abs() < 1e-4);
+        let (lights, total) = build(&spheres, &[], &[light], &[]);
+        assert_eq!(lights.len(), 1);
+        assert!((total - 12.0 * PI).abs() < 1e-3);
+    }
+}
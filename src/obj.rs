@@ -0,0 +1,212 @@
+use std::path::Path;
+
+use glam::{Mat4, Vec2, Vec3};
+
+use crate::{texture::TextureSet, utils::*};
+
+/// Loads every object in a Wavefront OBJ file as its own [`TriangleMesh`], with quads
+/// and n-gons fan-triangulated and vertices transformed by `transform`.
+///
+/// Vertex normals are taken from the file, or generated for objects that have none.
+/// Tangents are generated for objects with texture coordinates.
+///
+/// `usemtl` materials from the referenced MTL file are mapped onto [`Material`]; faces
+/// without one, or whose MTL file can't be read, get `default_material`. Their texture
+/// maps are added to `textures`, with unreadable ones skipped.
+pub fn load_obj(
+    path: impl AsRef<Path>,
+    transform: Mat4,
+    default_material: Material,
+    textures: &mut TextureSet,
+) -> Result<Vec<TriangleMesh>, tobj::LoadError> {
+    let path = path.as_ref();
+    let (models, materials) = tobj::load_obj(path, &tobj::GPU_LOAD_OPTIONS)?;
+    let materials = materials.unwrap_or_else(|err| {
+        eprintln!("{}: error loading materials: {err}", path.display());
+        Vec::new()
+    });
+    let base_dir = path.parent().unwrap_or(Path::new(""));
+    let materials = materials
+        .iter()
+        .map(|material| convert_material(material, base_dir, textures))
+        .collect::<Vec<_>>();
+    let normal_matrix = transform.inverse().transpose();
+
+    Ok(models
+        .into_iter()
+        .filter(|model| !model.mesh.indices.is_empty())
+        .map(|model| {
+            let mesh = model.mesh;
+            let mut vertices = mesh
+                .positions
+                .chunks_exact(3)
+                .map(|p| {
+                    Vertex::new(
+                        transform.transform_point3(Vec3::from_slice(p)),
+                        Vec3::ZERO,
+                        Vec2::ZERO,
+                    )
+                })
+                .collect::<Vec<_>>();
+            // OBJ puts v = 0 at the bottom of the image
+            for (vertex, uv) in vertices.iter_mut().zip(mesh.texcoords.chunks_exact(2)) {
+                vertex.u = uv[0];
+                vertex.v = 1.0 - uv[1];
+            }
+            if mesh.normals.is_empty() {
+                generate_normals(&mut vertices, &mesh.indices);
+            } else {
+                for (vertex, n) in vertices.iter_mut().zip(mesh.normals.chunks_exact(3)) {
+                    vertex.normal = normal_matrix
+                        .transform_vector3(Vec3::from_slice(n))
+                        .normalize_or_zero();
+                }
+            }
+            if !mesh.texcoords.is_empty() {
+                generate_tangents(&mut vertices, &mesh.indices);
+            }
+            let material = mesh
+                .material_id
+                .and_then(|id| materials.get(id).copied())
+                .unwrap_or(default_material);
+            TriangleMesh::indexed(vertices, mesh.indices, material)
+        })
+        .collect())
+}
+
+/// `Kd` becomes the diffuse color, `Ke` the emission (split into a normalized color and
+/// strength), `Ns` the roughness unless the PBR `Pr` is given, `Pm` the metalness, `Ni`
+/// the refractive index and `Tf` the color glass absorbs to per unit. Materials with a
+/// refraction illumination model (`illum` 4, 6, 7 or 9) are treated as glass, as are those
+/// with a dissolve `d` below 1 that also give an `Ni`; a dissolve alone usually means an
+/// alpha-blended cutout, which is left opaque. Glass without `Ni` uses
+/// [`DEFAULT_REFRACTIVE_INDEX`]. `map_Kd`, `map_Pr` and `map_Ke` are the diffuse,
+/// roughness and emission textures, and `norm` or `map_Bump` the normal map.
+fn convert_material(
+    material: &tobj::Material,
+    base_dir: &Path,
+    textures: &mut TextureSet,
+) -> Material {
+    let mut texture = |name: Option<&String>| {
+        name.map_or(NO_TEXTURE, |name| {
+            let path = base_dir.join(strip_options(name));
+            textures.load(&path).unwrap_or_else(|err| {
+                eprintln!("{}: error loading texture: {err}", path.display());
+                NO_TEXTURE
+            })
+        })
+    };
+    let param = |name: &str| {
+        material
+            .unknown_param
+            .get(name)
+            .and_then(|value| value.trim().parse::<f32>().ok())
+    };
+    let emission = material
+        .unknown_param
+        .get("Ke")
+        .and_then(|ke| parse_vec3(ke))
+        .unwrap_or(Vec3::ZERO);
+    let emission_strength = emission.max_element();
+    let transmission_filter = material
+        .unknown_param
+        .get("Tf")
+        .and_then(|tf| parse_vec3(tf));
+    let glass = matches!(material.illumination_model, Some(4 | 6 | 7 | 9))
+        || (material.dissolve.is_some_and(|d| d < 1.0) && material.optical_density.is_some());
+
+    Material {
+        diffuse_color: material.diffuse.map_or(Vec3::ONE, Vec3::from),
+        roughness: param("Pr").unwrap_or_else(|| {
+            // Ns is a Phong exponent, matched to the GGX lobe of similar width
+            material
+                .shininess
+                .map_or(1.0, |ns| (2.0 / (ns.max(0.0) + 2.0)).powf(0.25))
+        }),
+        metallic: param("Pm").unwrap_or(0.0),
+        absorption_color: transmission_filter.unwrap_or(Vec3::ONE),
+        absorption_density: if transmission_filter.is_some() {
+            1.0
+        } else {
+            0.0
+        },
+        emission_color: if emission_strength > 0.0 {
+            emission / emission_strength
+        } else {
+            Vec3::ZERO
+        },
+        emission_strength,
+        refractive_index: material.optical_density.unwrap_or(DEFAULT_REFRACTIVE_INDEX),
+        kind: if glass {
+            MaterialKind::Dielectric
+        } else {
+            MaterialKind::Diffuse
+        } as u32,
+        diffuse_texture: texture(material.diffuse_texture.as_ref()),
+        roughness_texture: texture(material.unknown_param.get("map_Pr")),
+        emission_texture: texture(material.unknown_param.get("map_Ke")),
+        normal_texture: texture(
+            material
+                .unknown_param
+                .get("norm")
+                .or(material.normal_texture.as_ref()),
+        ),
+        ..Default::default()
+    }
+}
+
+/// Drops leading options like `-bm 1.0` from a texture statement, leaving the file name.
+fn strip_options(statement: &str) -> &str {
+    let mut rest = statement.trim();
+    while rest.starts_with('-') {
+        let mut tokens = rest.splitn(3, char::is_whitespace);
+        tokens.next();
+        tokens.next();
+        rest = tokens.next().unwrap_or("").trim_start();
+    }
+    rest
+}
+
+fn parse_vec3(s: &str) -> Option<Vec3> {
+    let mut values = s.split_whitespace().map(|v| v.parse::<f32>().ok());
+    Some(Vec3::new(values.next()??, values.next()??, values.next()??))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert(material: tobj::Material) -> Material {
+        convert_material(&material, Path::new(""), &mut TextureSet::default())
+    }
+
+    #[test]
+    fn dissolve_alone_stays_opaque() {
+        let material = convert(tobj::Material {
+            dissolve: Some(0.5),
+            ..Default::default()
+        });
+        assert_eq!(material.kind(), MaterialKind::Diffuse);
+    }
+
+    #[test]
+    fn dissolve_with_index_is_glass() {
+        let material = convert(tobj::Material {
+            dissolve: Some(0.5),
+            optical_density: Some(1.33),
+            ..Default::default()
+        });
+        assert_eq!(material.kind(), MaterialKind::Dielectric);
+        assert_eq!(material.refractive_index, 1.33);
+    }
+
+    #[test]
+    fn refraction_model_without_index_is_glass() {
+        let material = convert(tobj::Material {
+            illumination_model: Some(7),
+            ..Default::default()
+        });
+        assert_eq!(material.kind(), MaterialKind::Dielectric);
+        assert_eq!(material.refractive_index, DEFAULT_REFRACTIVE_INDEX);
+    }
+}
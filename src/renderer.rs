@@ -0,0 +1,254 @@
+use glam::{Mat3A, Quat, Vec3};
+
+use crate::{Scene, compute::*, utils::*};
+
+/// Features the device passed to [`Renderer::new`] must have been created with.
+pub const REQUIRED_FEATURES: wgpu::Features =
+    wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Camera {
+    pub position: Vec3,
+    pub rotation: Quat,
+    pub light_dir: Vec3,
+    /// Photographic stops the image is brightened by, for scenes lit far below daylight.
+    pub exposure: f32,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self {
+            position: Vec3::new(0.0, 0.0, 5.0),
+            rotation: Quat::IDENTITY,
+            light_dir: Vec3::new(0.2, 1.0, 0.05).normalize(),
+            exposure: 0.0,
+        }
+    }
+}
+
+/// Owns the compute pipeline, scene buffers and accumulation texture. Every call to
+/// [`Renderer::render_frame`] adds one more frame to the running average until the
+/// scene, camera or size changes.
+pub struct Renderer {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    compute: ComputePipeline,
+    scene: SceneBuffers,
+    output: OutputTexture,
+    bind_group: wgpu::BindGroup,
+    camera: Camera,
+    sky: Sky,
+    spectral: bool,
+    sampler: SamplerKind,
+    width: u32,
+    height: u32,
+    accumulated_frames: u32,
+}
+
+impl Renderer {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, width: u32, height: u32) -> Self {
+        let compute = ComputePipeline::new(device, queue);
+        let scene = SceneBuffers::new(device, queue, &Scene::default());
+        let output = OutputTexture::new(device, width, height);
+        let bind_group = compute.bind_group(device, &output, &scene);
+        Self {
+            device: device.clone(),
+            queue: queue.clone(),
+            compute,
+            scene,
+            output,
+            bind_group,
+            camera: Camera::default(),
+            sky: Sky::default(),
+            spectral: false,
+            sampler: SamplerKind::default(),
+            width,
+            height,
+            accumulated_frames: 0,
+        }
+    }
+
+    /// Uploads the scene geometry, lights, textures, sky, environment map, spectral mode
+    /// and sampler. The scene's camera is left for the caller to apply with
+    /// [`Renderer::set_camera`].
+    pub fn set_scene(&mut self, scene: &Scene) {
+        self.scene = SceneBuffers::new(&self.device, &self.queue, scene);
+        self.sky = scene.sky;
+        self.spectral = scene.spectral;
+        self.sampler = scene.sampler;
+        self.bind_group = self
+            .compute
+            .bind_group(&self.device, &self.output, &self.scene);
+        self.accumulated_frames = 0;
+    }
+
+    /// Only restarts accumulation if the camera actually moved.
+    pub fn set_camera(&mut self, camera: Camera) {
+        if camera != self.camera {
+            self.camera = camera;
+            self.accumulated_frames = 0;
+        }
+    }
+
+    pub fn camera(&self) -> Camera {
+        self.camera
+    }
+
+    /// Restarts accumulation, as samples from different samplers don't mix evenly.
+    pub fn set_sampler(&mut self, sampler: SamplerKind) {
+        if sampler != self.sampler {
+            self.sampler = sampler;
+            self.accumulated_frames = 0;
+        }
+    }
+
+    pub fn sampler(&self) -> SamplerKind {
+        self.sampler
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        if width == 0 || height == 0 || (width, height) == (self.width, self.height) {
+            return;
+        }
+        self.width = width;
+        self.height = height;
+        self.output = OutputTexture::new(&self.device, width, height);
+        self.bind_group = self
+            .compute
+            .bind_group(&self.device, &self.output, &self.scene);
+        self.accumulated_frames = 0;
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn accumulated_frames(&self) -> u32 {
+        self.accumulated_frames
+    }
+
+    /// The accumulation texture, valid until the next [`Renderer::resize`].
+    pub fn output_view(&self) -> &wgpu::TextureView {
+        &self.output.view
+    }
+
+    pub fn render_frame(&mut self) {
+        self.accumulated_frames += 1;
+        let config_data = IParams {
+            camera_pos: self.camera.position,
+            random_seed: rand::random(),
+            camera_dir: Mat3A::from_quat(self.camera.rotation),
+            light_dir: self.camera.light_dir.normalize_or_zero(),
+            accumulated_frames: self.accumulated_frames,
+            width: self.width,
+            height: self.height,
+            sphere_count: self.scene.sphere_count,
+            triangle_mesh_count: self.scene.triangle_mesh_count,
+            sky: self.sky,
+            spectral: self.spectral as u32,
+            light_count: self.scene.light_count,
+            light_power: self.scene.light_power,
+            environment: self.scene.has_environment as u32,
+            exposure: self.camera.exposure,
+            sampler_kind: self.sampler as u32,
+            _pad: [0; 2],
+        };
+        self.queue
+            .write_buffer(&self.compute.config, 0, bytemuck::bytes_of(&config_data));
+        let mut encoder = self.device.create_command_encoder(&Default::default());
+        self.compute
+            .dispatch(&mut encoder, &self.bind_group, self.width, self.height);
+        self.queue.submit(Some(encoder.finish()));
+    }
+
+    /// Copies the accumulation texture back to the CPU as tightly packed RGBA8 rows.
+    pub fn read_back(&self) -> Vec<u8> {
+        let unpadded_bytes_per_row = self.width * 4;
+        let padded_bytes_per_row =
+            unpadded_bytes_per_row.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        let readback = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Readback Buffer"),
+            size: (padded_bytes_per_row * self.height) as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = self.device.create_command_encoder(&Default::default());
+        encoder.copy_texture_to_buffer(
+            self.output.texture.as_image_copy(),
+            wgpu::TexelCopyBufferInfo {
+                buffer: &readback,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(self.height),
+                },
+            },
+            wgpu::Extent3d {
+                width: self.width,
+                height: self.height,
+                depth_or_array_layers: 1,
+            },
+        );
+        self.queue.submit(Some(encoder.finish()));
+
+        let slice = readback.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |result| {
+            result.expect("error mapping readback buffer")
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+
+        let data = slice.get_mapped_range();
+        let pixels = data
+            .chunks_exact(padded_bytes_per_row as usize)
+            .flat_map(|row| &row[..unpadded_bytes_per_row as usize])
+            .copied()
+            .collect();
+        drop(data);
+        readback.unmap();
+        pixels
+    }
+}
+
+pub async fn request_device(adapter: &wgpu::Adapter) -> (wgpu::Device, wgpu::Queue) {
+    adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                label: None,
+                required_features: REQUIRED_FEATURES,
+                required_limits: Default::default(),
+                memory_hints: Default::default(),
+            },
+            None,
+        )
+        .await
+        .expect("error creating device")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_back_strips_row_padding() {
+        let instance = wgpu::Instance::new(&Default::default());
+        let Some(adapter) = pollster::block_on(instance.request_adapter(&Default::default()))
+        else {
+            eprintln!("no adapter, skipping");
+            return;
+        };
+        let (device, queue) = pollster::block_on(request_device(&adapter));
+
+        // 260 bytes a row, padded to 512 for the copy, and not a whole number of workgroups
+        let (width, height) = (65, 3);
+        let mut renderer = Renderer::new(&device, &queue, width, height);
+        renderer.render_frame();
+        let pixels = renderer.read_back();
+        assert_eq!(pixels.len(), (width * height * 4) as usize);
+        // an empty scene is all sky and ground, so padding read as pixels would show as black
+        for pixel in pixels.chunks_exact(4) {
+            assert_eq!(pixel[3], 255);
+            assert!(pixel[..3].iter().any(|&channel| channel > 0), "{pixel:?}");
+        }
+    }
+}
@@ -0,0 +1,738 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use glam::{EulerRot, Mat4, Quat, Vec3};
+use image::RgbaImage;
+use serde::Deserialize;
+
+use crate::{
+    Camera, color,
+    environment::Environment,
+    gltf,
+    ies::{IesError, IesProfile},
+    lights, obj,
+    texture::TextureSet,
+    utils::*,
+};
+
+/// Everything needed to render a frame, as loaded from a `.ron` scene file.
+#[derive(Default)]
+pub struct Scene {
+    pub spheres: Vec<Sphere>,
+    pub triangles: Vec<TriangleMesh>,
+    pub camera: Camera,
+    pub sky: Sky,
+    /// Replaces the sky and sun when set.
+    pub environment: Option<Environment>,
+    pub lights: Vec<AnalyticLight>,
+    /// Intensity distributions the lights' profile indices refer to.
+    pub profiles: Vec<IesProfile>,
+    /// Images the materials' texture indices refer to.
+    pub textures: Vec<RgbaImage>,
+    /// Trace one wavelength per path, for dispersion.
+    pub spectral: bool,
+    pub sampler: SamplerKind,
+}
+
+impl Scene {
+    /// Model and texture paths in the file are resolved relative to the file's directory. A `.obj`,
+    /// `.gltf` or `.glb` path is loaded as a scene containing just that model.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SceneError> {
+        let path = path.as_ref();
+        if is_model(path) {
+            let file = SceneFile {
+                models: vec![ModelDesc {
+                    path: path.to_path_buf(),
+                    material: None,
+                    position: Vec3::ZERO,
+                    rotation: Vec3::ZERO,
+                    scale: 1.0,
+                    flat_shading: false,
+                }],
+                ..Default::default()
+            };
+            return Self::build(file, Path::new(""));
+        }
+        let source = std::fs::read_to_string(path).map_err(SceneError::Io)?;
+        Self::parse(&source, path.parent().unwrap_or(Path::new("")))
+    }
+
+    /// Model and texture paths in the source are resolved relative to the working directory.
+    pub fn from_ron(source: &str) -> Result<Self, SceneError> {
+        Self::parse(source, Path::new(""))
+    }
+
+    fn parse(source: &str, base_dir: &Path) -> Result<Self, SceneError> {
+        // optional fields like `camera` can be written without `Some(..)`
+        let file: SceneFile = ron::Options::default()
+            .with_default_extension(ron::extensions::Extensions::IMPLICIT_SOME)
+            .from_str(source)
+            .map_err(SceneError::Parse)?;
+        Self::build(file, base_dir)
+    }
+
+    fn build(file: SceneFile, base_dir: &Path) -> Result<Self, SceneError> {
+        let mut textures = TextureSet::default();
+        let materials = file
+            .materials
+            .iter()
+            .map(|(name, desc)| Ok((name.as_str(), desc.to_material(base_dir, &mut textures)?)))
+            .collect::<Result<HashMap<_, _>, SceneError>>()?;
+        let material = |name: &str| {
+            materials
+                .get(name)
+                .copied()
+                .ok_or_else(|| SceneError::UnknownMaterial(name.to_string()))
+        };
+
+        let spheres = file
+            .spheres
+            .iter()
+            .map(|sphere| {
+                Ok(Sphere {
+                    position: sphere.position,
+                    radius: sphere.radius,
+                    material: material(&sphere.material)?,
+                })
+            })
+            .collect::<Result<_, SceneError>>()?;
+
+        let mut triangles = file
+            .meshes
+            .iter()
+            .map(|mesh| {
+                if mesh.vertices.len() % 3 != 0 {
+                    return Err(SceneError::InvalidMesh(mesh.vertices.len()));
+                }
+                let mut triangle_mesh =
+                    TriangleMesh::new(mesh.vertices.clone(), material(&mesh.material)?);
+                triangle_mesh.flat_shading = mesh.flat_shading;
+                Ok(triangle_mesh)
+            })
+            .collect::<Result<Vec<_>, SceneError>>()?;
+
+        let mut model_camera = None;
+        for model in &file.models {
+            let override_material = model.material.as_deref().map(material).transpose()?;
+            let path = base_dir.join(&model.path);
+            let meshes = if is_gltf(&path) {
+                let scene = gltf::load_gltf(&path, model.transform(), &mut textures)
+                    .map_err(|err| SceneError::Gltf(path, err))?;
+                model_camera = model_camera.or(scene.camera);
+                scene.meshes
+            } else {
+                obj::load_obj(
+                    &path,
+                    model.transform(),
+                    MaterialDesc::default().to_material(base_dir, &mut textures)?,
+                    &mut textures,
+                )
+                .map_err(|err| SceneError::Obj(path, err))?
+            };
+            triangles.extend(meshes.into_iter().map(|mut mesh| {
+                if let Some(material) = override_material {
+                    mesh.material = material;
+                }
+                mesh.flat_shading = model.flat_shading;
+                mesh
+            }));
+        }
+
+        // an explicit camera wins over one imported from a glTF file
+        let (position, rotation) = match (file.camera, model_camera) {
+            (None, Some(camera)) => camera,
+            (camera, _) => {
+                let camera = camera.unwrap_or_default();
+                (
+                    camera.position,
+                    Quat::from_rotation_y(camera.yaw.to_radians())
+                        * Quat::from_rotation_x(camera.pitch.to_radians()),
+                )
+            }
+        };
+        let camera = Camera {
+            position,
+            rotation,
+            light_dir: file.sky.light_dir.normalize_or_zero(),
+            exposure: file.camera.unwrap_or_default().exposure,
+        };
+
+        let sky = Sky {
+            ground_color: file.sky.ground_color,
+            sun_intensity: file.sky.sun_intensity,
+            horizon_color: file.sky.horizon_color,
+            sun_focus: file.sky.sun_focus,
+            zenith_color: file.sky.zenith_color,
+            sun_color: file.sky.sun_color,
+            environment_intensity: file.sky.environment_intensity,
+            environment_rotation: file.sky.environment_rotation.to_radians(),
+            turbidity: file.sky.turbidity,
+            daylight_intensity: file.sky.daylight_intensity,
+            sun_radius: file.sky.sun_radius.to_radians(),
+            _pad: 0.0,
+        };
+        let environment = file
+            .sky
+            .environment
+            .map(|path| {
+                let path = base_dir.join(path);
+                Environment::load(&path).map_err(|err| SceneError::Texture(path, err))
+            })
+            .transpose()?;
+        let mut profiles = Vec::new();
+        let mut profile_paths = HashMap::new();
+        let lights = file
+            .lights
+            .iter()
+            .map(|desc| {
+                let profile = match &desc.profile {
+                    Some(path) if desc.kind != LightKind::Directional => {
+                        let path = base_dir.join(path);
+                        match profile_paths.get(&path) {
+                            Some(&index) => index,
+                            None => {
+                                let profile = IesProfile::load(&path)
+                                    .map_err(|err| SceneError::Ies(path.clone(), err))?;
+                                profiles.push(profile);
+                                profile_paths.insert(path, profiles.len() as u32 - 1);
+                                profiles.len() as u32 - 1
+                            }
+                        }
+                    }
+                    _ => NO_PROFILE,
+                };
+                Ok(desc.to_light(profile, &profiles))
+            })
+            .collect::<Result<Vec<_>, SceneError>>()?;
+
+        Ok(Self {
+            spheres,
+            triangles,
+            camera,
+            sky,
+            environment,
+            lights,
+            profiles,
+            textures: textures.images,
+            spectral: file.spectral,
+            sampler: file.sampler,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(std::io::Error),
+    Parse(ron::error::SpannedError),
+    UnknownMaterial(String),
+    /// A mesh whose vertex count isn't a multiple of three.
+    InvalidMesh(usize),
+    Obj(PathBuf, tobj::LoadError),
+    Gltf(PathBuf, ::gltf::Error),
+    Texture(PathBuf, image::ImageError),
+    Ies(PathBuf, IesError),
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io(err) => write!(f, "error reading scene: {err}"),
+            SceneError::Parse(err) => write!(f, "error parsing scene: {err}"),
+            SceneError::UnknownMaterial(name) => write!(f, "unknown material \"{name}\""),
+            SceneError::InvalidMesh(count) => {
+                write!(f, "mesh has {count} vertices, expected a multiple of 3")
+            }
+            SceneError::Obj(path, err) => write!(f, "error loading {}: {err}", path.display()),
+            SceneError::Gltf(path, err) => write!(f, "error loading {}: {err}", path.display()),
+            SceneError::Texture(path, err) => {
+                write!(f, "error loading {}: {err}", path.display())
+            }
+            SceneError::Ies(path, err) => write!(f, "error loading {}: {err}", path.display()),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+#[derive(Default, Deserialize)]
+struct SceneFile {
+    #[serde(default)]
+    camera: Option<CameraDesc>,
+    #[serde(default)]
+    sky: SkyDesc,
+    #[serde(default)]
+    materials: HashMap<String, MaterialDesc>,
+    #[serde(default)]
+    spheres: Vec<SphereDesc>,
+    #[serde(default)]
+    meshes: Vec<MeshDesc>,
+    #[serde(default)]
+    models: Vec<ModelDesc>,
+    #[serde(default)]
+    lights: Vec<LightDesc>,
+    #[serde(default)]
+    spectral: bool,
+    #[serde(default)]
+    sampler: SamplerKind,
+}
+
+#[derive(Copy, Clone, Deserialize)]
+#[serde(default)]
+struct CameraDesc {
+    position: Vec3,
+    /// Degrees around the Y axis.
+    yaw: f32,
+    /// Degrees around the X axis, positive looks up.
+    pitch: f32,
+    /// Photographic stops.
+    exposure: f32,
+}
+
+impl Default for CameraDesc {
+    fn default() -> Self {
+        Self {
+            position: Camera::default().position,
+            yaw: 0.0,
+            pitch: 0.0,
+            exposure: 0.0,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct SkyDesc {
+    light_dir: Vec3,
+    ground_color: Vec3,
+    horizon_color: Vec3,
+    zenith_color: Vec3,
+    sun_color: Vec3,
+    sun_intensity: f32,
+    sun_focus: f32,
+    /// Equirectangular `.hdr` image to light the scene with instead.
+    environment: Option<PathBuf>,
+    environment_intensity: f32,
+    /// Degrees around the Y axis.
+    environment_rotation: f32,
+    /// Nonzero for the Preetham sky instead of the gradient and the sun's lobe.
+    turbidity: f32,
+    daylight_intensity: f32,
+    /// Degrees.
+    sun_radius: f32,
+}
+
+impl Default for SkyDesc {
+    fn default() -> Self {
+        let sky = Sky::default();
+        Self {
+            light_dir: Camera::default().light_dir,
+            ground_color: sky.ground_color,
+            horizon_color: sky.horizon_color,
+            zenith_color: sky.zenith_color,
+            sun_color: sky.sun_color,
+            sun_intensity: sky.sun_intensity,
+            sun_focus: sky.sun_focus,
+            environment: None,
+            environment_intensity: sky.environment_intensity,
+            environment_rotation: 0.0,
+            turbidity: sky.turbidity,
+            daylight_intensity: sky.daylight_intensity,
+            sun_radius: sky.sun_radius.to_degrees(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct MaterialDesc {
+    diffuse_color: Vec3,
+    roughness: f32,
+    metallic: f32,
+    /// Defaults to white when `temperature` or `luminance` is given, black otherwise.
+    emission_color: Option<Vec3>,
+    emission_strength: f32,
+    /// Kelvin of a blackbody whose color tints `emission_color`.
+    temperature: Option<f32>,
+    /// cd/m², replacing `emission_strength`.
+    luminance: Option<f32>,
+    refractive_index: f32,
+    absorption_color: Vec3,
+    absorption_density: f32,
+    cauchy_b: f32,
+    sellmeier_b: Vec3,
+    sellmeier_c: Vec3,
+    kind: MaterialKind,
+    /// Whether triangles can be hit from behind, see [`Material::TWO_SIDED`].
+    two_sided: bool,
+    diffuse_texture: Option<PathBuf>,
+    roughness_texture: Option<PathBuf>,
+    emission_texture: Option<PathBuf>,
+    normal_texture: Option<PathBuf>,
+}
+
+impl Default for MaterialDesc {
+    fn default() -> Self {
+        Self {
+            diffuse_color: Vec3::ONE,
+            roughness: 1.0,
+            metallic: 0.0,
+            emission_color: None,
+            emission_strength: 0.0,
+            temperature: None,
+            luminance: None,
+            refractive_index: 1.0,
+            absorption_color: Vec3::ONE,
+            absorption_density: 0.0,
+            cauchy_b: 0.0,
+            sellmeier_b: Vec3::ZERO,
+            sellmeier_c: Vec3::ZERO,
+            kind: MaterialKind::Diffuse,
+            two_sided: true,
+            diffuse_texture: None,
+            roughness_texture: None,
+            emission_texture: None,
+            normal_texture: None,
+        }
+    }
+}
+
+impl MaterialDesc {
+    fn to_material(
+        &self,
+        base_dir: &Path,
+        textures: &mut TextureSet,
+    ) -> Result<Material, SceneError> {
+        let mut texture = |path: &Option<PathBuf>| match path {
+            Some(path) => {
+                let path = base_dir.join(path);
+                textures
+                    .load(&path)
+                    .map_err(|err| SceneError::Texture(path, err))
+            }
+            None => Ok(NO_TEXTURE),
+        };
+        let photometric = self.temperature.is_some() || self.luminance.is_some();
+        let emission_color =
+            self.emission_color
+                .unwrap_or(if photometric { Vec3::ONE } else { Vec3::ZERO })
+                * self.temperature.map_or(Vec3::ONE, color::blackbody);
+        let emission_strength = match self.luminance {
+            Some(luminance) => from_photometric(luminance, emission_color.dot(color::LUMINANCE)),
+            None => self.emission_strength,
+        };
+        Ok(Material {
+            diffuse_color: self.diffuse_color,
+            roughness: self.roughness,
+            metallic: self.metallic,
+            absorption_color: self.absorption_color,
+            absorption_density: self.absorption_density,
+            sellmeier_b: self.sellmeier_b,
+            cauchy_b: self.cauchy_b,
+            sellmeier_c: self.sellmeier_c,
+            emission_color,
+            emission_strength,
+            refractive_index: self.refractive_index,
+            kind: self.kind as u32,
+            flags: if self.two_sided {
+                Material::TWO_SIDED
+            } else {
+                0
+            },
+            diffuse_texture: texture(&self.diffuse_texture)?,
+            roughness_texture: texture(&self.roughness_texture)?,
+            emission_texture: texture(&self.emission_texture)?,
+            normal_texture: texture(&self.normal_texture)?,
+            ..Default::default()
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct LightDesc {
+    kind: LightKind,
+    position: Vec3,
+    /// Which way spot and directional lights shine.
+    direction: Vec3,
+    color: Vec3,
+    intensity: f32,
+    /// Kelvin of a blackbody whose color tints `color`.
+    temperature: Option<f32>,
+    /// Luminous intensity of a point or spot light's brightest direction, replacing
+    /// `intensity`.
+    candela: Option<f32>,
+    /// Luminous flux of a point or spot light, replacing `intensity`.
+    lumens: Option<f32>,
+    /// Illuminance from a directional light, replacing `intensity`.
+    lux: Option<f32>,
+    /// Degrees off `direction` where a spot light starts to fade.
+    inner_angle: f32,
+    /// Degrees off `direction` where a spot light has faded out.
+    outer_angle: f32,
+    /// Degrees, for soft shadows from directional lights.
+    angular_radius: f32,
+    /// `.ies` file whose measured distribution shapes a point or spot light, facing along
+    /// `direction`.
+    profile: Option<PathBuf>,
+}
+
+impl Default for LightDesc {
+    fn default() -> Self {
+        Self {
+            kind: LightKind::Point,
+            position: Vec3::ZERO,
+            direction: Vec3::NEG_Y,
+            color: Vec3::ONE,
+            intensity: 1.0,
+            temperature: None,
+            candela: None,
+            lumens: None,
+            lux: None,
+            inner_angle: 30.0,
+            outer_angle: 45.0,
+            angular_radius: 0.0,
+            profile: None,
+        }
+    }
+}
+
+impl LightDesc {
+    fn to_light(&self, profile: u32, profiles: &[IesProfile]) -> AnalyticLight {
+        let outer_angle = match self.kind {
+            LightKind::Directional => self.angular_radius,
+            _ => self.outer_angle,
+        };
+        let color = self.color * self.temperature.map_or(Vec3::ONE, color::blackbody);
+        let mut light = AnalyticLight {
+            position: self.position,
+            kind: self.kind as u32,
+            direction: self.direction.normalize_or(Vec3::NEG_Y),
+            cos_inner: self.inner_angle.min(self.outer_angle).to_radians().cos(),
+            intensity: color,
+            cos_outer: outer_angle.to_radians().cos(),
+            profile,
+            _pad: [0; 3],
+        };
+        let luminance = color.dot(color::LUMINANCE);
+        let intensity = match (self.kind, self.candela, self.lumens, self.lux) {
+            (LightKind::Directional, _, _, Some(lux)) => from_photometric(lux, luminance),
+            (LightKind::Directional, ..) => self.intensity,
+            (_, Some(candela), _, _) => from_photometric(candela, luminance),
+            // spread over the same solid angle, and profile, that its share of power assumes
+            (_, None, Some(lumens), _) => {
+                from_photometric(lumens, lights::analytic_power(&light, 0.0, profiles))
+            }
+            _ => self.intensity,
+        };
+        light.intensity *= intensity;
+        light
+    }
+}
+
+#[derive(Deserialize)]
+struct SphereDesc {
+    position: Vec3,
+    radius: f32,
+    material: String,
+}
+
+#[derive(Deserialize)]
+struct MeshDesc {
+    /// Flat list of triangles, three vertices each.
+    vertices: Vec<Vec3>,
+    material: String,
+    #[serde(default)]
+    flat_shading: bool,
+}
+
+#[derive(Deserialize)]
+struct ModelDesc {
+    path: PathBuf,
+    /// Replaces every material from the model's MTL file.
+    #[serde(default)]
+    material: Option<String>,
+    #[serde(default)]
+    position: Vec3,
+    /// Degrees, applied in Y, X, Z order.
+    #[serde(default)]
+    rotation: Vec3,
+    #[serde(default = "default_scale")]
+    scale: f32,
+    /// Ignores the model's vertex normals and shades every face flat.
+    #[serde(default)]
+    flat_shading: bool,
+}
+
+impl ModelDesc {
+    fn transform(&self) -> Mat4 {
+        let rotation = Quat::from_euler(
+            EulerRot::YXZ,
+            self.rotation.y.to_radians(),
+            self.rotation.x.to_radians(),
+            self.rotation.z.to_radians(),
+        );
+        Mat4::from_scale_rotation_translation(Vec3::splat(self.scale), rotation, self.position)
+    }
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+/// Scale that gives an emitter whose color has `luminance` the photometric `value` in
+/// the renderer's units, see [`color::DAYLIGHT_UNIT`].
+fn from_photometric(value: f32, luminance: f32) -> f32 {
+    if luminance > 0.0 {
+        value / color::DAYLIGHT_UNIT / luminance
+    } else {
+        0.0
+    }
+}
+
+fn is_gltf(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("gltf") || ext.eq_ignore_ascii_case("glb"))
+}
+
+fn is_model(path: &Path) -> bool {
+    is_gltf(path)
+        || path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("obj"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    /// Distinct materials, and light-emitting objects plus analytic lights, expected in each
+    /// bundled scene.
+    const BUNDLED: &[(&str, usize, usize)] = &[
+        ("blackbody.ron", 4, 5),
+        ("cornell.ron", 7, 3),
+        ("daylight.ron", 4, 0),
+        ("ies.ron", 3, 4),
+        ("lights.ron", 5, 3),
+        ("models.ron", 3, 2),
+        ("prism.ron", 3, 0),
+        ("spheres.ron", 7, 0),
+        ("studio.ron", 4, 0),
+        ("textures.ron", 5, 0),
+    ];
+
+    fn counts(scene: &Scene) -> (usize, usize) {
+        let materials = scene
+            .spheres
+            .iter()
+            .map(|sphere| sphere.material)
+            .chain(scene.triangles.iter().map(|mesh| mesh.material))
+            .collect::<Vec<_>>();
+        let distinct = materials
+            .iter()
+            .map(|material| bytemuck::bytes_of(material).to_vec())
+            .collect::<HashSet<_>>()
+            .len();
+        let lights = materials
+            .iter()
+            .filter(|material| {
+                material.emission_strength > 0.0 && material.emission_color != Vec3::ZERO
+            })
+            .count();
+        (distinct, lights + scene.lights.len())
+    }
+
+    #[test]
+    fn bundled_scenes_load() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("assets/scenes");
+        let mut loaded = 0;
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let name = path.file_name().unwrap().to_str().unwrap();
+            let &(_, materials, lights) = BUNDLED
+                .iter()
+                .find(|(file, ..)| *file == name)
+                .unwrap_or_else(|| panic!("{name} is missing from BUNDLED"));
+            let scene = Scene::load(&path).unwrap_or_else(|err| panic!("{name}: {err}"));
+            assert_eq!(counts(&scene), (materials, lights), "{name}");
+            loaded += 1;
+        }
+        assert_eq!(loaded, BUNDLED.len());
+    }
+
+    #[test]
+    fn unknown_material_is_reported() {
+        let source = r#"(spheres: [(position: (0.0, 0.0, 0.0), radius: 1.0, material: "gold")])"#;
+        assert!(matches!(
+            Scene::from_ron(source),
+            Err(SceneError::UnknownMaterial(name)) if name == "gold"
+        ));
+    }
+
+    #[test]
+    fn partial_triangle_is_reported() {
+        let source = r#"(
+            materials: {"white": ()},
+            meshes: [(vertices: [(0.0, 0.0, 0.0), (1.0, 0.0, 0.0)], material: "white")],
+        )"#;
+        assert!(matches!(
+            Scene::from_ron(source),
+            Err(SceneError::InvalidMesh(2))
+        ));
+    }
+
+    /// Photometric value a light's final intensity stands for, undoing the conversion.
+    fn photometric(light: &AnalyticLight) -> f32 {
+        light.intensity.dot(color::LUMINANCE) * color::DAYLIGHT_UNIT
+    }
+
+    #[test]
+    fn candela_round_trips_through_the_daylight_unit() {
+        let desc = LightDesc {
+            candela: Some(800.0),
+            color: Vec3::new(1.0, 0.5, 0.25),
+            ..Default::default()
+        };
+        let light = desc.to_light(NO_PROFILE, &[]);
+        assert!((photometric(&light) - 800.0).abs() < 1e-2);
+    }
+
+    #[test]
+    fn lux_round_trips_through_the_daylight_unit() {
+        let desc = LightDesc {
+            kind: LightKind::Directional,
+            lux: Some(1e5),
+            temperature: Some(3000.0),
+            ..Default::default()
+        };
+        let light = desc.to_light(NO_PROFILE, &[]);
+        assert!((photometric(&light) / 1e5 - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn lumens_round_trip_through_the_light_power() {
+        let desc = LightDesc {
+            kind: LightKind::Spot,
+            lumens: Some(1200.0),
+            ..Default::default()
+        };
+        let light = desc.to_light(NO_PROFILE, &[]);
+        let lumens = lights::analytic_power(&light, 0.0, &[]) * color::DAYLIGHT_UNIT;
+        assert!((lumens / 1200.0 - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn black_emitters_get_no_photometric_scale() {
+        assert_eq!(from_photometric(100.0, 0.0), 0.0);
+        let desc = LightDesc {
+            candela: Some(100.0),
+            color: Vec3::ZERO,
+            ..Default::default()
+        };
+        assert_eq!(desc.to_light(NO_PROFILE, &[]).intensity, Vec3::ZERO);
+    }
+}
@@ -0,0 +1,61 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use glam::{Vec2, Vec4};
+use image::RgbaImage;
+
+/// Layers of the GPU texture array are never larger than this in either dimension.
+pub const MAX_LAYER_SIZE: u32 = 2048;
+
+/// The images a scene's materials refer to by index, with files only loaded once.
+#[derive(Default)]
+pub struct TextureSet {
+    pub images: Vec<RgbaImage>,
+    paths: HashMap<PathBuf, u32>,
+}
+
+impl TextureSet {
+    pub fn load(&mut self, path: impl AsRef<Path>) -> Result<u32, image::ImageError> {
+        let path = path.as_ref();
+        if let Some(&index) = self.paths.get(path) {
+            return Ok(index);
+        }
+        let index = self.push(image::open(path)?.to_rgba8());
+        self.paths.insert(path.to_path_buf(), index);
+        Ok(index)
+    }
+
+    pub fn push(&mut self, image: RgbaImage) -> u32 {
+        self.images.push(image);
+        self.images.len() as u32 - 1
+    }
+}
+
+/// The size every image is scaled to so they fit in one texture array: the largest
+/// width and height among them, capped at [`MAX_LAYER_SIZE`].
+pub fn layer_size(images: &[RgbaImage]) -> (u32, u32) {
+    images.iter().fold((1, 1), |(w, h), image| {
+        (
+            w.max(image.width()).min(MAX_LAYER_SIZE),
+            h.max(image.height()).min(MAX_LAYER_SIZE),
+        )
+    })
+}
+
+/// Bilinear lookup with repeat wrapping, matching the shader's sampler.
+pub fn sample(image: &RgbaImage, uv: Vec2) -> Vec4 {
+    let size = Vec2::new(image.width() as f32, image.height() as f32);
+    let p = uv * size - 0.5;
+    let base = p.floor();
+    let t = p - base;
+    let texel = |dx: f32, dy: f32| {
+        let x = ((base.x + dx).rem_euclid(size.x) as u32).min(image.width() - 1);
+        let y = ((base.y + dy).rem_euclid(size.y) as u32).min(image.height() - 1);
+        Vec4::from_array(image.get_pixel(x, y).0.map(|c| c as f32 / 255.0))
+    };
+    let top = texel(0.0, 0.0).lerp(texel(1.0, 0.0), t.x);
+    let bottom = texel(0.0, 1.0).lerp(texel(1.0, 1.0), t.x);
+    top.lerp(bottom, t.y)
+}
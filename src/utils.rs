@@ -10,9 +10,65 @@ pub struct IParams {
     pub accumulated_frames: u32,
     pub width: u32,
     pub height: u32,
+    pub sphere_count: u32,
+    pub triangle_mesh_count: u32,
+    pub sky: Sky,
+    /// Nonzero traces one wavelength per path instead of RGB.
+    pub spectral: u32,
+    pub light_count: u32,
+    /// Summed power of all lights, see [`crate::lights::build`].
+    pub light_power: f32,
+    /// Nonzero lights the scene with the environment map instead of the sky and sun.
+    pub environment: u32,
+    /// Photographic stops every pixel is brightened by.
+    pub exposure: f32,
+    /// Where random numbers come from, see [`SamplerKind`].
+    pub sampler_kind: u32,
     pub _pad: [u32; 2],
 }
 
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Sky {
+    pub ground_color: Vec3,
+    pub sun_intensity: f32,
+    pub horizon_color: Vec3,
+    pub sun_focus: f32,
+    pub zenith_color: Vec3,
+    /// Scales the environment map, if there is one.
+    pub environment_intensity: f32,
+    pub sun_color: Vec3,
+    /// Radians the environment map is turned by around the Y axis.
+    pub environment_rotation: f32,
+    /// Nonzero replaces the gradient and the sun's lobe with the Preetham daylight model
+    /// for air this hazy, 2 being clear and 10 overcast.
+    pub turbidity: f32,
+    /// Scales the Preetham sky and sun, which are 1 for a white surface under the sun.
+    pub daylight_intensity: f32,
+    /// Angular radius of the Preetham sun's disk in radians.
+    pub sun_radius: f32,
+    pub _pad: f32,
+}
+
+impl Default for Sky {
+    fn default() -> Self {
+        Self {
+            ground_color: Vec3::new(0.35, 0.3, 0.35),
+            sun_intensity: 10.0,
+            horizon_color: Vec3::new(1.0, 1.0, 1.0),
+            sun_focus: 500.0,
+            zenith_color: Vec3::new(0.08, 0.37, 0.73),
+            environment_intensity: 1.0,
+            sun_color: Vec3::new(1.0, 0.9, 0.6),
+            environment_rotation: 0.0,
+            turbidity: 0.0,
+            daylight_intensity: 1.0,
+            sun_radius: 0.2666f32.to_radians(),
+            _pad: 0.0,
+        }
+    }
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct Sphere {
@@ -24,17 +80,181 @@ pub struct Sphere {
 #[repr(C)]
 #[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct GPUTriangleMesh {
-    pub _pad: Vec2,
-    pub start_index: u32,
-    pub vertex_count: u32,
-    pub aabb: Aabb,
+    pub bvh_root: u32,
+    /// Added to every index, so indices stay local to the mesh.
+    pub base_vertex: u32,
+    pub first_index: u32,
+    pub index_count: u32,
+    pub flat_shading: u32,
+    pub _pad: [u32; 3],
     pub material: Material,
 }
 
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Vertex {
+    pub position: Vec3,
+    pub u: f32,
+    pub normal: Vec3,
+    pub v: f32,
+    /// Direction of increasing `u`, with the bitangent `normal.cross(tangent) * w`
+    /// pointing up the image. Zero when the mesh has no UVs to derive it from.
+    pub tangent: Vec4,
+}
+
+impl Vertex {
+    pub fn new(position: Vec3, normal: Vec3, uv: Vec2) -> Self {
+        Self {
+            position,
+            u: uv.x,
+            normal,
+            v: uv.y,
+            tangent: Vec4::ZERO,
+        }
+    }
+
+    pub fn uv(&self) -> Vec2 {
+        Vec2::new(self.u, self.v)
+    }
+}
+
 pub struct TriangleMesh {
-    pub vertices: Vec<Vec3>,
+    /// Unique vertices, shared between triangles.
+    pub vertices: Vec<Vertex>,
+    /// Three per triangle, stored in leaf order of `bvh`.
+    pub indices: Vec<u32>,
     pub aabb: Aabb,
     pub material: Material,
+    /// Shades with the face normal instead of interpolating the vertex normals.
+    pub flat_shading: bool,
+    pub bvh: Vec<BvhNode>,
+}
+
+impl TriangleMesh {
+    /// Indexes a flat triangle list, three vertices per triangle, with generated normals.
+    pub fn new(positions: Vec<Vec3>, material: Material) -> Self {
+        let indices = (0..positions.len() as u32).collect::<Vec<_>>();
+        let mut vertices = positions
+            .into_iter()
+            .map(|position| Vertex::new(position, Vec3::ZERO, Vec2::ZERO))
+            .collect::<Vec<_>>();
+        generate_normals(&mut vertices, &indices);
+        Self::indexed(vertices, indices, material)
+    }
+
+    /// Merges identical vertices, then builds the mesh's BVH and reorders the triangles
+    /// in `indices` to match it.
+    pub fn indexed(vertices: Vec<Vertex>, indices: Vec<u32>, material: Material) -> Self {
+        let (vertices, indices) = dedup_vertices(&vertices, &indices);
+        let (bvh, order) = crate::bvh::build(&vertices, &indices);
+        let indices = order
+            .iter()
+            .flat_map(|&i| {
+                let i = i as usize * 3;
+                [indices[i], indices[i + 1], indices[i + 2]]
+            })
+            .collect::<Vec<_>>();
+        let positions = vertices.iter().map(|v| v.position).collect::<Vec<_>>();
+        Self {
+            aabb: Aabb::from_points(&positions),
+            vertices,
+            indices,
+            material,
+            flat_shading: false,
+            bvh,
+        }
+    }
+
+    pub fn triangle(&self, index: usize) -> [Vertex; 3] {
+        [0, 1, 2].map(|j| self.vertices[self.indices[index * 3 + j] as usize])
+    }
+}
+
+/// Sets every vertex normal to the average of the faces around its position, weighted
+/// by the angle of each face's corner, so the result doesn't depend on how the faces
+/// were triangulated. Vertices at the same position share a normal even if they aren't
+/// shared in `indices`.
+pub fn generate_normals(vertices: &mut [Vertex], indices: &[u32]) {
+    let mut normals = std::collections::HashMap::new();
+    for tri in indices.chunks_exact(3) {
+        let p = [0, 1, 2].map(|j| vertices[tri[j] as usize].position);
+        let face_normal = (p[1] - p[0]).cross(p[2] - p[0]).normalize_or_zero();
+        for j in 0..3 {
+            let angle = (p[(j + 1) % 3] - p[j]).angle_between(p[(j + 2) % 3] - p[j]);
+            if angle.is_finite() {
+                *normals
+                    .entry(p[j].to_array().map(f32::to_bits))
+                    .or_insert(Vec3::ZERO) += face_normal * angle;
+            }
+        }
+    }
+    for vertex in vertices {
+        vertex.normal = normals
+            .get(&vertex.position.to_array().map(f32::to_bits))
+            .map_or(Vec3::ZERO, |n| n.normalize_or_zero());
+    }
+}
+
+/// Derives every vertex tangent from how the UVs run across the triangles around it.
+/// Needs the normals to be set already.
+pub fn generate_tangents(vertices: &mut [Vertex], indices: &[u32]) {
+    let mut directions = vec![(Vec3::ZERO, Vec3::ZERO); vertices.len()];
+    for tri in indices.chunks_exact(3) {
+        let [v0, v1, v2] = [0, 1, 2].map(|j| vertices[tri[j] as usize]);
+        let (e1, e2) = (v1.position - v0.position, v2.position - v0.position);
+        let (d1, d2) = (v1.uv() - v0.uv(), v2.uv() - v0.uv());
+        let r = 1.0 / (d1.x * d2.y - d2.x * d1.y);
+        if !r.is_finite() {
+            continue;
+        }
+        let u_dir = (e1 * d2.y - e2 * d1.y) * r;
+        let v_dir = (e2 * d1.x - e1 * d2.x) * r;
+        for &i in tri {
+            directions[i as usize].0 += u_dir;
+            directions[i as usize].1 += v_dir;
+        }
+    }
+    for (vertex, (u_dir, v_dir)) in vertices.iter_mut().zip(directions) {
+        let n = vertex.normal;
+        let t = (u_dir - n * n.dot(u_dir)).normalize_or_zero();
+        // uv v runs down the image, the bitangent up
+        let w = if n.cross(t).dot(v_dir) > 0.0 {
+            -1.0
+        } else {
+            1.0
+        };
+        vertex.tangent = t.extend(w);
+    }
+}
+
+/// Keeps the first of every set of bit-identical vertices and drops unreferenced ones.
+fn dedup_vertices(vertices: &[Vertex], indices: &[u32]) -> (Vec<Vertex>, Vec<u32>) {
+    let mut unique = Vec::new();
+    let mut lookup = std::collections::HashMap::new();
+    let indices = indices
+        .iter()
+        .map(|&i| {
+            let vertex = vertices[i as usize];
+            *lookup
+                .entry(bytemuck::cast::<_, [u32; 12]>(vertex))
+                .or_insert_with(|| {
+                    unique.push(vertex);
+                    unique.len() as u32 - 1
+                })
+        })
+        .collect();
+    (unique, indices)
+}
+
+/// A leaf when `count > 0`, covering triangles `left_first..left_first + count`,
+/// otherwise an interior node whose children are `left_first` and `left_first + 1`.
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct BvhNode {
+    pub min: Vec3,
+    pub left_first: u32,
+    pub max: Vec3,
+    pub count: u32,
 }
 
 #[repr(C)]
@@ -44,14 +264,393 @@ pub struct Aabb {
     pub max: Vec4,
 }
 
+impl Aabb {
+    pub fn from_points(points: &[Vec3]) -> Self {
+        let (min, max) = points
+            .iter()
+            .fold((Vec3::INFINITY, Vec3::NEG_INFINITY), |(min, max), &p| {
+                (min.min(p), max.max(p))
+            });
+        Self {
+            min: min.extend(0.0),
+            max: max.extend(0.0),
+        }
+    }
+}
+
+/// An emissive sphere or triangle, or an analytic light, that paths sample directly.
 #[repr(C)]
 #[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Light {
+    /// A sphere index, a mesh index offset by the sphere count, or [`ANALYTIC_LIGHT`].
+    pub object: u32,
+    /// Triangle within the mesh or index of the analytic light, unused for spheres.
+    pub triangle: u32,
+    /// Chance of picking this light.
+    pub probability: f32,
+    /// Sum of the probabilities up to and including this light.
+    pub cdf: f32,
+}
+
+/// [`Light::object`] of lights in the scene's list of [`AnalyticLight`]s.
+pub const ANALYTIC_LIGHT: u32 = u32::MAX;
+
+/// A light without a surface, which paths can only reach with shadow rays. Intensity is
+/// the radiance times solid angle it delivers: falling off with the squared distance from
+/// point and spot lights, and constant from directional ones.
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct AnalyticLight {
+    pub position: Vec3,
+    /// A [`LightKind`].
+    pub kind: u32,
+    /// Which way spot and directional lights shine.
+    pub direction: Vec3,
+    /// Cosine of the angle off `direction` where a spot light starts to fade.
+    pub cos_inner: f32,
+    pub intensity: Vec3,
+    /// Cosine of the angle off `direction` where a spot light has faded out, or of a
+    /// directional light's angular radius.
+    pub cos_outer: f32,
+    /// Index of the [`crate::ies::IesProfile`] shaping a point or spot light's intensity
+    /// around `direction`, or [`NO_PROFILE`].
+    pub profile: u32,
+    pub _pad: [u32; 3],
+}
+
+/// [`AnalyticLight::profile`] of lights that shine the same in every direction.
+pub const NO_PROFILE: u32 = u32::MAX;
+
+/// What shape of light an [`AnalyticLight`] is.
+#[repr(u32)]
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq, serde::Deserialize)]
+pub enum LightKind {
+    /// Shines equally in every direction from its position.
+    #[default]
+    Point = 0,
+    /// A point light limited to a cone, fading smoothly between its inner and outer angle.
+    Spot = 1,
+    /// Shines from infinitely far away, like the sun, from within its angular radius.
+    Directional = 2,
+}
+
+impl LightKind {
+    pub fn from_u32(kind: u32) -> Option<Self> {
+        [Self::Point, Self::Spot, Self::Directional]
+            .into_iter()
+            .find(|k| *k as u32 == kind)
+    }
+}
+
+/// Where the renderer's random numbers come from, stored in [`IParams::sampler_kind`].
+#[repr(u32)]
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq, serde::Deserialize)]
+pub enum SamplerKind {
+    /// Independent numbers from a hash of the pixel and a new seed every frame.
+    Random = 0,
+    /// Owen-scrambled Sobol points, evenly covering every pair of dimensions as samples
+    /// accumulate.
+    #[default]
+    Sobol = 1,
+    /// Evenly spread numbers per sample that differ between neighboring pixels by a
+    /// blue-noise tile, so the remaining noise is fine grained and easy on the eye.
+    BlueNoise = 2,
+}
+
+impl SamplerKind {
+    pub fn from_u32(kind: u32) -> Option<Self> {
+        [Self::Random, Self::Sobol, Self::BlueNoise]
+            .into_iter()
+            .find(|k| *k as u32 == kind)
+    }
+}
+
+/// Texture index of a material without that texture.
+pub const NO_TEXTURE: u32 = u32::MAX;
+
+/// How a surface scatters light, stored in [`Material::kind`].
+#[repr(u32)]
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq, serde::Deserialize)]
+pub enum MaterialKind {
+    /// GGX microfacet reflection over a diffuse base, blending into a metal by `metallic`.
+    #[default]
+    Diffuse = 0,
+    /// Like `Diffuse` with `metallic` at 1: reflects only, tinted by `diffuse_color`.
+    Metal = 1,
+    /// Glass-like, refracting by `refractive_index` and tinted by `absorption_color` with
+    /// depth.
+    Dielectric = 2,
+    /// Only emits light, paths that hit it end there.
+    Emissive = 3,
+}
+
+impl MaterialKind {
+    pub fn from_u32(kind: u32) -> Option<Self> {
+        [Self::Diffuse, Self::Metal, Self::Dielectric, Self::Emissive]
+            .into_iter()
+            .find(|k| *k as u32 == kind)
+    }
+}
+
+/// Textures are indices into the scene's texture list and modulate the constant above
+/// them: diffuse and emission are multiplied by the sRGB color, the roughness texture's
+/// green channel scales `roughness` and its blue channel `metallic`, as in glTF. The
+/// normal texture is a tangent-space normal map with +Y pointing up the image.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct Material {
+    /// Albedo of dielectric surfaces, and the reflectance of metals.
     pub diffuse_color: Vec3,
-    pub smoothness: f32,
+    /// Perceptual GGX roughness, squared to get the distribution's alpha.
+    pub roughness: f32,
     pub emission_color: Vec3,
     pub emission_strength: f32,
     pub refractive_index: f32,
-    pub flag: u32,
-    pub _pad: [u32; 2],
+    /// A [`MaterialKind`].
+    pub kind: u32,
+    /// Any of the `Material::TWO_SIDED`-style bits.
+    pub flags: u32,
+    pub diffuse_texture: u32,
+    pub roughness_texture: u32,
+    pub emission_texture: u32,
+    pub normal_texture: u32,
+    pub metallic: f32,
+    /// What white light turns into after one unit inside a dielectric at density 1.
+    pub absorption_color: Vec3,
+    /// Scales the distance light travels inside a dielectric, 0 makes it clear.
+    pub absorption_density: f32,
+    /// Sellmeier B coefficients of a dielectric. When any is nonzero they replace
+    /// `refractive_index` and `cauchy_b`.
+    pub sellmeier_b: Vec3,
+    /// Cauchy B coefficient in µm², making the refractive index `refractive_index +
+    /// cauchy_b / λ²`.
+    pub cauchy_b: f32,
+    /// Sellmeier C coefficients in µm².
+    pub sellmeier_c: Vec3,
+    pub _pad: f32,
+}
+
+impl Material {
+    /// Triangles can be hit from behind as well as from the front, where the front is the
+    /// side their vertices wind counter-clockwise around.
+    pub const TWO_SIDED: u32 = 1 << 0;
+
+    pub fn kind(&self) -> MaterialKind {
+        MaterialKind::from_u32(self.kind).unwrap_or_default()
+    }
+
+    pub fn two_sided(&self) -> bool {
+        self.flags & Self::TWO_SIDED != 0
+    }
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            diffuse_color: Vec3::ZERO,
+            roughness: 1.0,
+            emission_color: Vec3::ZERO,
+            emission_strength: 0.0,
+            refractive_index: 0.0,
+            kind: MaterialKind::Diffuse as u32,
+            flags: Material::TWO_SIDED,
+            diffuse_texture: NO_TEXTURE,
+            roughness_texture: NO_TEXTURE,
+            emission_texture: NO_TEXTURE,
+            normal_texture: NO_TEXTURE,
+            metallic: 0.0,
+            absorption_color: Vec3::ONE,
+            absorption_density: 0.0,
+            sellmeier_b: Vec3::ZERO,
+            cauchy_b: 0.0,
+            sellmeier_c: Vec3::ZERO,
+            _pad: 0.0,
+        }
+    }
+}
+
+/// Refractive index of glass, for imported dielectrics that don't give their own.
+pub const DEFAULT_REFRACTIVE_INDEX: f32 = 1.5;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex(x: f32, normal: Vec3) -> Vertex {
+        Vertex::new(Vec3::new(x, 0.0, 0.0), normal, Vec2::ZERO)
+    }
+
+    fn positions(positions: &[Vec3]) -> Vec<Vertex> {
+        positions
+            .iter()
+            .map(|&position| Vertex::new(position, Vec3::ZERO, Vec2::ZERO))
+            .collect()
+    }
+
+    #[test]
+    fn dedup_merges_identical_vertices() {
+        let vertices = [
+            vertex(0.0, Vec3::Z),
+            vertex(1.0, Vec3::Z),
+            vertex(0.0, Vec3::Z),
+            vertex(2.0, Vec3::Z),
+        ];
+        let (unique, indices) = dedup_vertices(&vertices, &[0, 1, 3, 2, 3, 1]);
+        assert_eq!(unique.len(), 3);
+        assert_eq!(indices, [0, 1, 2, 0, 2, 1]);
+        for (&i, &original) in indices.iter().zip(&[0, 1, 3, 2, 3, 1]) {
+            assert_eq!(unique[i as usize], vertices[original]);
+        }
+    }
+
+    #[test]
+    fn dedup_keeps_vertices_differing_in_any_attribute() {
+        // same position, different normal, as along a hard edge
+        let vertices = [vertex(0.0, Vec3::Z), vertex(0.0, Vec3::Y)];
+        let (unique, indices) = dedup_vertices(&vertices, &[0, 1]);
+        assert_eq!(unique.len(), 2);
+        assert_eq!(indices, [0, 1]);
+    }
+
+    #[test]
+    fn dedup_drops_unreferenced_vertices() {
+        let vertices = [
+            vertex(0.0, Vec3::Z),
+            vertex(1.0, Vec3::Z),
+            vertex(2.0, Vec3::Z),
+        ];
+        let (unique, indices) = dedup_vertices(&vertices, &[2, 0, 2]);
+        assert_eq!(unique.len(), 2);
+        assert_eq!(indices, [0, 1, 0]);
+        assert_eq!(unique[0].position.x, 2.0);
+    }
+
+    #[test]
+    fn triangle_soup_shares_corners() {
+        // a flat quad as two separate triangles
+        let mesh = TriangleMesh::new(
+            vec![
+                Vec3::ZERO,
+                Vec3::X,
+                Vec3::ONE,
+                Vec3::ZERO,
+                Vec3::ONE,
+                Vec3::Y,
+            ],
+            Material::default(),
+        );
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.indices.len(), 6);
+    }
+
+    #[test]
+    fn normals_are_weighted_by_corner_angle() {
+        // three faces of a cube meeting at the origin, one split into two triangles there
+        let mut vertices = positions(&[
+            Vec3::ZERO,
+            Vec3::X,
+            Vec3::Y,
+            Vec3::Z,
+            Vec3::new(1.0, 1.0, 0.0),
+        ]);
+        let split = [0, 2, 4, 0, 4, 1, 0, 1, 3, 0, 3, 2];
+        generate_normals(&mut vertices, &split);
+        let corner = -Vec3::ONE.normalize();
+        assert!(
+            vertices[0].normal.abs_diff_eq(corner, 1e-5),
+            "{}",
+            vertices[0].normal
+        );
+        // the far corner of the split face only touches that face
+        assert!(vertices[4].normal.abs_diff_eq(Vec3::NEG_Z, 1e-5));
+    }
+
+    #[test]
+    fn normals_are_shared_by_position() {
+        // a bent strip whose triangles don't share vertices
+        let mut vertices = positions(&[Vec3::ZERO, Vec3::Y, Vec3::X, Vec3::ZERO, Vec3::Z, Vec3::Y]);
+        generate_normals(&mut vertices, &[0, 1, 2, 3, 4, 5]);
+        assert_eq!(vertices[0].normal, vertices[3].normal);
+        assert!(
+            vertices[0]
+                .normal
+                .abs_diff_eq(Vec3::new(-1.0, 0.0, -1.0).normalize(), 1e-5)
+        );
+        assert!(vertices[2].normal.abs_diff_eq(Vec3::NEG_Z, 1e-5));
+    }
+
+    #[test]
+    fn degenerate_triangles_give_no_normal() {
+        let mut vertices = positions(&[Vec3::ZERO, Vec3::X, Vec3::X * 2.0]);
+        generate_normals(&mut vertices, &[0, 1, 2]);
+        assert!(vertices.iter().all(|vertex| vertex.normal == Vec3::ZERO));
+    }
+
+    /// A triangle facing +Z, with `u` running along `u_axis` and `v` down the image (-Y).
+    fn uv_triangle(u_axis: f32) -> Vec<Vertex> {
+        [
+            (Vec3::ZERO, Vec2::new(0.0, 1.0)),
+            (Vec3::X, Vec2::new(u_axis, 1.0)),
+            (Vec3::Y, Vec2::ZERO),
+        ]
+        .map(|(position, uv)| Vertex::new(position, Vec3::Z, uv))
+        .to_vec()
+    }
+
+    #[test]
+    fn tangents_follow_u_with_the_bitangent_up_the_image() {
+        let mut vertices = uv_triangle(1.0);
+        generate_tangents(&mut vertices, &[0, 1, 2]);
+        for vertex in &vertices {
+            assert!(
+                vertex
+                    .tangent
+                    .abs_diff_eq(Vec4::new(1.0, 0.0, 0.0, 1.0), 1e-5)
+            );
+            let bitangent = vertex.normal.cross(vertex.tangent.truncate()) * vertex.tangent.w;
+            assert!(bitangent.abs_diff_eq(Vec3::Y, 1e-5));
+        }
+    }
+
+    #[test]
+    fn mirrored_uvs_flip_the_handedness() {
+        let mut vertices = uv_triangle(-1.0);
+        generate_tangents(&mut vertices, &[0, 1, 2]);
+        for vertex in &vertices {
+            assert!(
+                vertex
+                    .tangent
+                    .abs_diff_eq(Vec4::new(-1.0, 0.0, 0.0, -1.0), 1e-5)
+            );
+            let bitangent = vertex.normal.cross(vertex.tangent.truncate()) * vertex.tangent.w;
+            assert!(bitangent.abs_diff_eq(Vec3::Y, 1e-5));
+        }
+    }
+
+    #[test]
+    fn tangents_are_perpendicular_to_the_normal() {
+        let mut vertices = uv_triangle(1.0);
+        let tilted = Vec3::new(0.5, 0.0, 1.0).normalize();
+        vertices
+            .iter_mut()
+            .for_each(|vertex| vertex.normal = tilted);
+        generate_tangents(&mut vertices, &[0, 1, 2]);
+        let tangent = vertices[0].tangent.truncate();
+        assert!(tangent.dot(tilted).abs() < 1e-5);
+        assert!((tangent.length() - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn collapsed_uvs_give_no_tangent() {
+        let mut vertices = uv_triangle(1.0);
+        vertices
+            .iter_mut()
+            .for_each(|vertex| (vertex.u, vertex.v) = (0.5, 0.5));
+        generate_tangents(&mut vertices, &[0, 1, 2]);
+        assert!(
+            vertices
+                .iter()
+                .all(|vertex| vertex.tangent.truncate() == Vec3::ZERO)
+        );
+    }
 }